@@ -1,99 +1,515 @@
 use std::io;
 use std::io::Read;
-use std::collections::HashMap;
-use std::str::Lines;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Display;
+use std::ops::RangeBounds;
 
 const TOTAL_SIZE: u32 = 70000000;
 const SIZE_REQUIRED: u32 = 30000000;
+const ROOT: DirId = 0;
 
-#[derive(Debug)]
+type DirId = usize;
+
+#[derive(Debug, Default)]
 struct Directory {
-    subdirectories: HashMap<String, Box<Directory>>,
-    files: HashMap<String, File>,
+    name: String,
+    parent: Option<DirId>,
+    subdirectories: BTreeMap<String, DirId>,
+    files: BTreeMap<String, File>,
+    /// transcript line on which the directory first appeared
+    first_seen: usize,
+    listed: bool,
 }
 
-impl Directory {
-    fn size(&self) -> u32 {
-        let mut ret = 0;
-        ret += self.files.values().map(|f| f.size).sum::<u32>();
-        ret += self.subdirectories.values().map(|d| d.size()).sum::<u32>();
-        ret
+#[derive(Debug)]
+struct File {
+    size: u32,
+}
+
+#[derive(Debug, PartialEq)]
+enum Problem {
+    Unrecognised,
+    UnknownDirectory(String),
+    AboveRoot,
+    ConflictingSize{name: String, previous: u32, size: u32},
+    OutputWithoutLs,
+    NeverListed(String),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Unrecognised => write!(f, "unrecognised line"),
+            Problem::UnknownDirectory(name) => write!(f, "cd into unknown directory {name}"),
+            Problem::AboveRoot => write!(f, "cd .. from the root directory"),
+            Problem::ConflictingSize{name, previous, size} =>
+                write!(f, "{name} listed with size {size}, previously {previous}"),
+            Problem::OutputWithoutLs => write!(f, "listing output without a preceding $ ls"),
+            Problem::NeverListed(path) => write!(f, "{path} was never listed, so its size is unknown"),
+        }
     }
 }
 
-#[derive(Debug)]
-struct File {
+/// a problem found while replaying a transcript, with its 1-based line number
+#[derive(Debug, PartialEq)]
+struct Report {
+    line: usize,
+    text: String,
+    problem: Problem,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}: `{}`", self.line, self.problem, self.text)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Objective {
+    FewestBytes,
+    FewestDeletions,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Deletion {
+    Directory(DirId),
+    File(DirId, String),
+}
+
+#[derive(Debug, PartialEq)]
+struct Plan {
+    deletions: Vec<Deletion>,
+    freed: u32,
+}
+
+/// something that could be deleted, in pre-order: deleting it skips
+/// straight to `end`, past everything nested inside it
+struct Candidate {
+    deletion: Deletion,
     size: u32,
+    end: usize,
+}
+
+struct Planner {
+    candidates: Vec<Candidate>,
+    /// bytes still reachable from each position onwards
+    reachable: Vec<u32>,
+    needed: u32,
+    objective: Objective,
+    best: Option<((u64, u64), Vec<usize>)>,
+}
+
+impl Planner {
+    /// lower is better: the objective's quantity first, then the other one
+    /// to break ties
+    fn score(&self, freed: u32, count: usize) -> (u64, u64) {
+        let (freed, count) = (u64::from(freed), count as u64);
+        match self.objective {
+            Objective::FewestBytes => (freed, count),
+            Objective::FewestDeletions => (count, freed),
+        }
+    }
+
+    fn search(&mut self, position: usize, freed: u32, chosen: &mut Vec<usize>) {
+        let lower_bound = if freed >= self.needed {
+            self.score(freed, chosen.len())
+        } else {
+            self.score(self.needed, chosen.len() + 1)
+        };
+        if self.best.as_ref().is_some_and(|(best, _)| lower_bound >= *best) {
+            return;
+        }
+        if freed >= self.needed {
+            self.best = Some((lower_bound, chosen.clone()));
+            return;
+        }
+        if position == self.candidates.len() || freed + self.reachable[position] < self.needed {
+            return;
+        }
+        chosen.push(position);
+        let candidate = &self.candidates[position];
+        self.search(candidate.end, freed + candidate.size, chosen);
+        chosen.pop();
+        self.search(position + 1, freed, chosen);
+    }
+}
+
+/// directories are stored in an arena, indexed by DirId, with the root at 0.
+/// A directory is always created after its parent, so walking the arena
+/// backwards visits children before parents.
+#[derive(Debug)]
+struct FileSystem {
+    dirs: Vec<Directory>,
+    sizes: Vec<u32>,
+}
+
+impl FileSystem {
+    fn new() -> Self {
+        Self{
+            dirs: vec![Directory{name: "/".to_string(), first_seen: 1, ..Default::default()}],
+            sizes: vec![0],
+        }
+    }
+
+    fn parse(input: &str) -> Self {
+        Self::replay(input).0
+    }
+
+    /// replay a transcript, building as much of the filesystem as possible
+    /// and reporting anything inconsistent along the way
+    fn replay(input: &str) -> (Self, Vec<Report>) {
+        let mut fs = Self::new();
+        let mut reports = vec![];
+        let mut cwd = ROOT;
+        let mut listing = false;
+        for (n, line) in input.lines().enumerate().map(|(i, l)| (i + 1, l)) {
+            let mut report = |problem| reports.push(Report{line: n, text: line.to_string(), problem});
+            let mut contents = line.split(' ');
+            match (contents.next(), contents.next(), contents.next(), contents.next()) {
+                (Some("$"), Some("cd"), Some("/"), None) => {
+                    cwd = ROOT;
+                    listing = false;
+                },
+                (Some("$"), Some("cd"), Some(".."), None) => {
+                    match fs.dirs[cwd].parent {
+                        Some(parent) => cwd = parent,
+                        None => report(Problem::AboveRoot),
+                    }
+                    listing = false;
+                },
+                (Some("$"), Some("cd"), Some(name), None) => {
+                    if !fs.dirs[cwd].subdirectories.contains_key(name) {
+                        report(Problem::UnknownDirectory(name.to_string()));
+                    }
+                    cwd = fs.subdirectory(cwd, name, n);
+                    listing = false;
+                },
+                (Some("$"), Some("ls"), None, None) => {
+                    fs.dirs[cwd].listed = true;
+                    listing = true;
+                },
+                (Some("dir"), Some(name), None, None) => {
+                    if !listing {
+                        report(Problem::OutputWithoutLs);
+                    }
+                    fs.subdirectory(cwd, name, n);
+                },
+                (Some(size), Some(name), None, None) if size.parse::<u32>().is_ok() => {
+                    if !listing {
+                        report(Problem::OutputWithoutLs);
+                    }
+                    let size = size.parse::<u32>().unwrap();
+                    let previous = fs.dirs[cwd].files.insert(name.to_string(), File{size});
+                    if let Some(previous) = previous.filter(|f| f.size != size) {
+                        report(Problem::ConflictingSize{name: name.to_string(), previous: previous.size, size});
+                    }
+                },
+                _ => report(Problem::Unrecognised),
+            }
+        }
+        for (id, dir) in fs.dirs.iter().enumerate() {
+            if !dir.listed {
+                reports.push(Report{
+                    line: dir.first_seen,
+                    text: input.lines().nth(dir.first_seen - 1).unwrap_or_default().to_string(),
+                    problem: Problem::NeverListed(fs.path(id)),
+                });
+            }
+        }
+        reports.sort_by_key(|r| r.line);
+        fs.update_sizes();
+        (fs, reports)
+    }
+
+    /// the id of the named subdirectory, creating it if it hasn't been seen yet
+    fn subdirectory(&mut self, parent: DirId, name: &str, line: usize) -> DirId {
+        if let Some(&id) = self.dirs[parent].subdirectories.get(name) {
+            return id;
+        }
+        let id = self.dirs.len();
+        self.dirs.push(Directory{name: name.to_string(), parent: Some(parent), first_seen: line, ..Default::default()});
+        self.dirs[parent].subdirectories.insert(name.to_string(), id);
+        id
+    }
+
+    fn update_sizes(&mut self) {
+        self.sizes = vec![0; self.dirs.len()];
+        for id in (0..self.dirs.len()).rev() {
+            let dir = &self.dirs[id];
+            self.sizes[id] += dir.files.values().map(|f| f.size).sum::<u32>();
+            if let Some(parent) = dir.parent {
+                self.sizes[parent] += self.sizes[id];
+            }
+        }
+    }
+
+    fn dir_size(&self, id: DirId) -> u32 {
+        self.sizes[id]
+    }
+
+    /// resolve an absolute path such as "/a/e"
+    fn lookup(&self, path: &str) -> Option<DirId> {
+        let mut id = ROOT;
+        for name in path.split('/').filter(|s| !s.is_empty()) {
+            id = *self.dirs[id].subdirectories.get(name)?;
+        }
+        Some(id)
+    }
+
+    fn size(&self, path: &str) -> Option<u32> {
+        self.lookup(path).map(|id| self.dir_size(id))
+    }
+
+    fn path(&self, id: DirId) -> String {
+        let mut names = vec![];
+        let mut current = id;
+        while let Some(parent) = self.dirs[current].parent {
+            names.push(self.dirs[current].name.as_str());
+            current = parent;
+        }
+        names.reverse();
+        format!("/{}", names.join("/"))
+    }
+
+    /// all directories whose total size falls in the given range
+    fn find(&self, range: impl RangeBounds<u32>) -> Vec<DirId> {
+        (0..self.dirs.len()).filter(|&id| range.contains(&self.dir_size(id))).collect()
+    }
+
+    /// the cheapest set of non-nested directories (and optionally files) to delete
+    /// so that `required` bytes are free on a disk of `disk_size` bytes
+    fn plan_deletion(&self, disk_size: u32, required: u32, include_files: bool, objective: Objective) -> Option<Plan> {
+        let free = disk_size.saturating_sub(self.dir_size(ROOT));
+        let mut planner = Planner{
+            candidates: vec![],
+            reachable: vec![],
+            needed: required.saturating_sub(free),
+            objective,
+            best: None,
+        };
+        let mut own = vec![];
+        self.add_candidates(ROOT, include_files, &mut planner.candidates, &mut own);
+        planner.reachable = own.iter().rev().scan(0, |total, size| { *total += size; Some(*total) }).collect();
+        planner.reachable.reverse();
+        planner.search(0, 0, &mut vec![]);
+        planner.best.map(|(_, chosen)| {
+            let deletions: Vec<Deletion> = chosen.iter().map(|&i| planner.candidates[i].deletion.clone()).collect();
+            let freed = chosen.iter().map(|&i| planner.candidates[i].size).sum();
+            Plan{deletions, freed}
+        })
+    }
+
+    /// `own` holds the bytes only removable by deleting that candidate or an ancestor
+    fn add_candidates(&self, id: DirId, include_files: bool, candidates: &mut Vec<Candidate>, own: &mut Vec<u32>) {
+        let dir = &self.dirs[id];
+        let position = candidates.len();
+        candidates.push(Candidate{deletion: Deletion::Directory(id), size: self.dir_size(id), end: 0});
+        if include_files {
+            own.push(0);
+            for (name, file) in &dir.files {
+                candidates.push(Candidate{deletion: Deletion::File(id, name.clone()), size: file.size, end: candidates.len() + 1});
+                own.push(file.size);
+            }
+        } else {
+            own.push(dir.files.values().map(|f| f.size).sum());
+        }
+        for &child in dir.subdirectories.values() {
+            self.add_candidates(child, include_files, candidates, own);
+        }
+        candidates[position].end = candidates.len();
+    }
+
+    fn describe(&self, deletion: &Deletion) -> String {
+        match deletion {
+            Deletion::Directory(id) => format!("{}\t{}", self.dir_size(*id), self.path(*id)),
+            Deletion::File(id, name) => {
+                let path = self.path(*id);
+                let separator = if path.ends_with('/') { "" } else { "/" };
+                format!("{}\t{path}{separator}{name}", self.dirs[*id].files[name].size)
+            },
+        }
+    }
+
+    /// one line per directory, children before parents, like `du`
+    fn du(&self) -> String {
+        let mut ret = String::new();
+        self.du_from(ROOT, &mut ret);
+        ret
+    }
+
+    fn du_from(&self, id: DirId, out: &mut String) {
+        for &child in self.dirs[id].subdirectories.values() {
+            self.du_from(child, out);
+        }
+        out.push_str(&format!("{}\t{}\n", self.dir_size(id), self.path(id)));
+    }
+
+    fn fmt_tree(&self, id: DirId, depth: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let indent = "  ".repeat(depth);
+        let dir = &self.dirs[id];
+        writeln!(f, "{indent}- {} (dir, size={})", dir.name, self.dir_size(id))?;
+        for &child in dir.subdirectories.values() {
+            self.fmt_tree(child, depth + 1, f)?;
+        }
+        for (name, file) in &dir.files {
+            writeln!(f, "{indent}  - {name} (file, size={})", file.size)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for FileSystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_tree(ROOT, 0, f)
+    }
 }
 
 fn main() {
     let mut buf = String::new();
     io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
-    let fs = process_dir(&mut buf.lines());
-    let dirs = get_all_dirs(&fs);
-    let size_sum: u32 = dirs.iter().map(|d| d.size()).filter(|n| *n <= 100000).sum();
-    let space_to_reclaim = SIZE_REQUIRED - (TOTAL_SIZE - fs.size());
-    let smallest_dirsize = dirs.iter().map(|d| d.size()).filter(|n| *n >= space_to_reclaim)
-        .min().unwrap();
-
-    println!("Combined size of directories smaller than 100000: {}", size_sum);
-    println!("Size of smallest directory that can be deleted to free enough space: {}", smallest_dirsize);
-}
-
-fn process_dir(lines: &mut Lines) -> Directory {
-    let mut ret = Directory{
-        subdirectories: HashMap::new(),
-        files: HashMap::new(),
-    };
-    let iter = lines;
-    while let Some(line) = iter.next() {
-        let mut contents = line.split(" ");
-        match contents.next() {
-            Some("$") => {
-                match contents.next() {
-                    Some("cd") => {
-                        match contents.next() {
-                            Some("/") => {},
-                            Some("..") => {
-                                break;
-                            },
-                            Some(dir) => {
-                                ret.subdirectories.insert(dir.to_string(), Box::new(process_dir(iter)));
-                            },
-                            None => unreachable!(),
-                        }
-                    },
-                    Some("ls") => {
-                        // do nothing
-                    },
-                    _ => unreachable!(),
-                }
-            },
-            Some("dir") => {
-                // do nothing
-            },
-            Some(size) => {
-                if let Some(name) = contents.next() {
-                    ret.files.insert(
-                        name.to_string(),
-                        File{
-                            size: size.parse::<u32>().unwrap(),
-                        }
-                    );
-                }
-            },
-            _ => unreachable!(),
-        }
+    let fs = FileSystem::parse(&buf);
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+        ["tree"] => print!("{fs}"),
+        ["validate"] => {
+            let (_, reports) = FileSystem::replay(&buf);
+            for report in &reports {
+                println!("{report}");
+            }
+            println!("{} problems found", reports.len());
+        },
+        ["du"] => print!("{}", fs.du()),
+        ["plan", ref flags @ ..] => {
+            let objective = if flags.contains(&"--fewest-deletions") {
+                Objective::FewestDeletions
+            } else {
+                Objective::FewestBytes
+            };
+            match fs.plan_deletion(TOTAL_SIZE, SIZE_REQUIRED, flags.contains(&"--files"), objective) {
+                Some(plan) => {
+                    for deletion in &plan.deletions {
+                        println!("{}", fs.describe(deletion));
+                    }
+                    println!("{} bytes freed", plan.freed);
+                },
+                None => println!("Not enough space can be freed"),
+            }
+        },
+        ["size", path] => match fs.size(path) {
+            Some(size) => println!("{size}"),
+            None => println!("No such directory: {path}"),
+        },
+        ["find", "--min-size", size] => {
+            for id in fs.find(size.parse::<u32>().unwrap()..) {
+                println!("{}\t{}", fs.dir_size(id), fs.path(id));
+            }
+        },
+        _ => {
+            let size_sum: u32 = fs.find(..=100000).iter().map(|&id| fs.dir_size(id)).sum();
+            let smallest_dirsize = fs.plan_deletion(TOTAL_SIZE, SIZE_REQUIRED, false, Objective::FewestDeletions)
+                .unwrap().freed;
+
+            println!("Combined size of directories smaller than 100000: {}", size_sum);
+            println!("Size of smallest directory that can be deleted to free enough space: {}", smallest_dirsize);
+        },
     }
-    ret
 }
 
-fn get_all_dirs(dir: &Directory) -> Vec<&Directory> {
-    let mut ret = vec![dir];
-    for d in dir.subdirectories.values() {
-        for d2 in get_all_dirs(&d) {
-            ret.push(d2);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../example.txt");
+
+    #[test]
+    fn example_sizes() {
+        let fs = FileSystem::parse(EXAMPLE);
+        assert_eq!(fs.size("/a/e"), Some(584));
+        assert_eq!(fs.size("/a"), Some(94853));
+        assert_eq!(fs.size("/d"), Some(24933642));
+        assert_eq!(fs.size("/"), Some(48381165));
+        assert_eq!(fs.size("/x"), None);
+    }
+
+    #[test]
+    fn revisiting_does_not_duplicate() {
+        let fs = FileSystem::parse("$ cd /\n$ ls\ndir a\n$ cd a\n$ ls\n10 f\n$ cd /\n$ ls\ndir a\n$ cd a\n$ ls\n10 f\n");
+        assert_eq!(fs.dirs.len(), 2);
+        assert_eq!(fs.size("/"), Some(10));
+    }
+
+    #[test]
+    fn example_is_consistent() {
+        let (_, reports) = FileSystem::replay(EXAMPLE);
+        assert_eq!(reports, vec![]);
+    }
+
+    #[test]
+    fn inconsistent_transcript() {
+        let input = "$ cd /\n$ ls\ndir a\n5 b\n$ cd c\n7 d\n$ cd /\n$ ls\n6 b\n$ cd ..\n$ rm b\n";
+        let (fs, reports) = FileSystem::replay(input);
+        let problems: Vec<(usize, Problem)> = reports.into_iter().map(|r| (r.line, r.problem)).collect();
+        assert_eq!(problems, vec![
+            (3, Problem::NeverListed("/a".to_string())),
+            (5, Problem::UnknownDirectory("c".to_string())),
+            (5, Problem::NeverListed("/c".to_string())),
+            (6, Problem::OutputWithoutLs),
+            (9, Problem::ConflictingSize{name: "b".to_string(), previous: 5, size: 6}),
+            (10, Problem::AboveRoot),
+            (11, Problem::Unrecognised),
+        ]);
+        assert_eq!(fs.size("/"), Some(13));
+    }
+
+    #[test]
+    fn report_names_line() {
+        let (_, reports) = FileSystem::replay("$ cd /\n$ ls\n$ cd x\n$ ls\n");
+        assert_eq!(reports[0].to_string(), "line 3: cd into unknown directory x: `$ cd x`");
+    }
+
+    #[test]
+    fn plan_single_directory() {
+        let fs = FileSystem::parse(EXAMPLE);
+        let plan = fs.plan_deletion(TOTAL_SIZE, SIZE_REQUIRED, false, Objective::FewestDeletions).unwrap();
+        assert_eq!(plan.freed, 24933642);
+        assert_eq!(plan.deletions, vec![Deletion::Directory(fs.lookup("/d").unwrap())]);
+    }
+
+    #[test]
+    fn plan_fewest_bytes() {
+        // needs 10 bytes: /a alone is 12, but /b and /c together are exactly 10
+        let fs = FileSystem::parse("$ cd /\n$ ls\ndir a\ndir b\ndir c\n$ cd a\n$ ls\n12 f\n$ cd ..\n$ cd b\n$ ls\n6 g\n$ cd /\n$ cd c\n$ ls\n4 h\n");
+        let plan = fs.plan_deletion(22, 10, false, Objective::FewestBytes).unwrap();
+        assert_eq!(plan.freed, 10);
+        assert_eq!(plan.deletions, vec![Deletion::Directory(fs.lookup("/b").unwrap()), Deletion::Directory(fs.lookup("/c").unwrap())]);
+        let plan = fs.plan_deletion(22, 10, false, Objective::FewestDeletions).unwrap();
+        assert_eq!(plan.freed, 12);
+        assert_eq!(plan.deletions.len(), 1);
+    }
+
+    #[test]
+    fn plan_with_files() {
+        let fs = FileSystem::parse(EXAMPLE);
+        let needed = SIZE_REQUIRED - (TOTAL_SIZE - fs.dir_size(ROOT));
+        let plan = fs.plan_deletion(TOTAL_SIZE, SIZE_REQUIRED, true, Objective::FewestBytes).unwrap();
+        assert!(plan.freed >= needed);
+        assert!(plan.freed < 24933642);
+        assert_eq!(fs.plan_deletion(TOTAL_SIZE, TOTAL_SIZE + 1, true, Objective::FewestBytes), None);
+        assert_eq!(fs.plan_deletion(TOTAL_SIZE, 0, true, Objective::FewestBytes).unwrap().deletions, vec![]);
+    }
+
+    #[test]
+    fn find_by_size() {
+        let fs = FileSystem::parse(EXAMPLE);
+        let paths: Vec<String> = fs.find(..=100000).into_iter().map(|id| fs.path(id)).collect();
+        assert_eq!(paths, vec!["/a", "/a/e"]);
+    }
+
+    #[test]
+    fn tree_output() {
+        let fs = FileSystem::parse("$ cd /\n$ ls\ndir a\n5 b\n$ cd a\n$ ls\n3 c\n");
+        assert_eq!(fs.to_string(), "- / (dir, size=8)\n  - a (dir, size=3)\n    - c (file, size=3)\n  - b (file, size=5)\n");
+        assert_eq!(fs.du(), "3\t/a\n8\t/\n");
     }
-    ret
 }
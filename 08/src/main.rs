@@ -1,7 +1,8 @@
 use std::io;
 use std::io::Read;
+use std::env;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 enum Dir {
     Up,
     Down,
@@ -10,64 +11,215 @@ enum Dir {
 }
 
 const DIRECTIONS: [Dir; 4] = [Dir::Up, Dir::Down, Dir::Left, Dir::Right];
+/// heatmap shades from lowest to highest scenic score
+const SHADES: [char; 10] = [' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+/// restrictions on where a treehouse may be built
+#[derive(Default, Debug)]
+struct Constraints {
+    min_height: u8,
+    visible: bool,
+    exclude_border: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct Site {
+    point: (usize, usize),
+    height: u8,
+    score: usize,
+    /// viewing distance in each of DIRECTIONS
+    distances: [usize; 4],
+}
 
 fn main() {
     let mut buf = String::new();
     io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
     let pts = string_to_points(&buf);
-    println!("{} trees are visible", visible(&pts));
-    println!("Max scenic score: {}", max_scenic_score(&pts));
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+        ["sites", count, ref flags @ ..] => {
+            let mut constraints = Constraints::default();
+            let mut flags = flags.iter();
+            while let Some(&flag) = flags.next() {
+                match flag {
+                    "--min-height" => constraints.min_height = flags.next().unwrap().parse().unwrap(),
+                    "--visible" => constraints.visible = true,
+                    "--no-border" => constraints.exclude_border = true,
+                    _ => panic!("Unknown option {flag}"),
+                }
+            }
+            for site in best_sites(&pts, count.parse().unwrap(), &constraints) {
+                println!("{:?} height {} score {}: up {}, down {}, left {}, right {}",
+                    site.point, site.height, site.score,
+                    site.distances[0], site.distances[1], site.distances[2], site.distances[3]);
+            }
+        },
+        ["heatmap"] => print!("{}", heatmap(&scenic_scores(&pts))),
+        _ => {
+            println!("{} trees are visible", visible(&pts));
+            println!("Max scenic score: {}", max_scenic_score(&pts));
+        },
+    }
 }
 
-fn points_iter(pts: &Vec<Vec<u8>>) -> impl Iterator<Item = (usize, usize, u8)> {
-    let mut flat = vec![];
-    for (i, row) in pts.iter().enumerate() {
-        for (j, v) in row.iter().enumerate() {
-            flat.push((i, j, *v));
+/// the line of sight for `dir` runs along each line in `step` order, so that
+/// every tree has already seen all the trees between it and the edge
+struct Sweep {
+    lines: usize,
+    length: usize,
+    dir: Dir,
+    rows: usize,
+    cols: usize,
+}
+
+impl Sweep {
+    fn new(dir: Dir, rows: usize, cols: usize) -> Self {
+        match dir {
+            Dir::Left | Dir::Right => Self{lines: rows, length: cols, dir, rows, cols},
+            Dir::Up | Dir::Down => Self{lines: cols, length: rows, dir, rows, cols},
+        }
+    }
+
+    fn point(&self, line: usize, step: usize) -> (usize, usize) {
+        match self.dir {
+            Dir::Left => (line, step),
+            Dir::Right => (line, self.cols - 1 - step),
+            Dir::Up => (step, line),
+            Dir::Down => (self.rows - 1 - step, line),
         }
     }
-    flat.into_iter()
 }
 
-fn visible(pts: &Vec<Vec<u8>>) -> u32 {
-    let mut visible = 0;
-    for (i, j, _) in points_iter(pts) {
-        for dir in DIRECTIONS {
-            if visible_from(dir, &(i, j), pts) {
-                visible += 1;
-                break;
+fn dimensions(pts: &[Vec<u8>]) -> (usize, usize) {
+    (pts.len(), pts.first().map_or(0, |row| row.len()))
+}
+
+fn visible(pts: &[Vec<u8>]) -> u32 {
+    visibility_mask(pts).iter().flatten().filter(|&&v| v).count() as u32
+}
+
+/// whether each tree can be seen from outside the grid
+fn visibility_mask(pts: &[Vec<u8>]) -> Vec<Vec<bool>> {
+    let (rows, cols) = dimensions(pts);
+    let mut mask = vec![vec![false; cols]; rows];
+    for dir in DIRECTIONS {
+        let sweep = Sweep::new(dir, rows, cols);
+        for line in 0..sweep.lines {
+            let mut tallest = None;
+            for step in 0..sweep.length {
+                let (i, j) = sweep.point(line, step);
+                if tallest.is_none_or(|t| pts[i][j] > t) {
+                    mask[i][j] = true;
+                    tallest = Some(pts[i][j]);
+                }
             }
         }
     }
-    visible
+    mask
 }
 
-fn max_scenic_score(pts: &Vec<Vec<u8>>) -> usize {
-    let mut scores = vec![];
-    for (i, j, _) in points_iter(pts) {
-        scores.push(scenic_score((i, j), pts));
+fn max_scenic_score(pts: &[Vec<u8>]) -> usize {
+    *scenic_scores(pts).iter().flatten().max().unwrap()
+}
+
+fn scenic_scores(pts: &[Vec<u8>]) -> Vec<Vec<usize>> {
+    let (rows, cols) = dimensions(pts);
+    let mut scores = vec![vec![1; cols]; rows];
+    for dir in DIRECTIONS {
+        let sweep = Sweep::new(dir, rows, cols);
+        // steps along the line with strictly decreasing heights
+        let mut stack: Vec<(usize, u8)> = Vec::with_capacity(10);
+        for line in 0..sweep.lines {
+            stack.clear();
+            for step in 0..sweep.length {
+                let (i, j) = sweep.point(line, step);
+                let height = pts[i][j];
+                while stack.last().is_some_and(|&(_, h)| h < height) {
+                    stack.pop();
+                }
+                let distance = match stack.last() {
+                    Some(&(blocker, h)) => {
+                        let distance = step - blocker;
+                        if h == height {
+                            stack.pop();
+                        }
+                        distance
+                    },
+                    None => step,
+                };
+                scores[i][j] *= distance;
+                stack.push((step, height));
+            }
+        }
     }
-    *scores.iter().max().unwrap()
+    scores
 }
 
-fn scenic_score((i, j): (usize, usize), pts: &Vec<Vec<u8>>) -> usize {
-    [Dir::Up, Dir::Down, Dir::Left, Dir::Right].iter().map(|&dir|
-    viewing_distance(dir, (i, j), &pts)).product()
+/// the `count` best places for a treehouse, highest scenic score first
+fn best_sites(pts: &[Vec<u8>], count: usize, constraints: &Constraints) -> Vec<Site> {
+    let (rows, cols) = dimensions(pts);
+    let scores = scenic_scores(pts);
+    let mask = if constraints.visible { visibility_mask(pts) } else { vec![] };
+    let mut candidates = vec![];
+    for (i, row) in scores.iter().enumerate() {
+        for (j, &score) in row.iter().enumerate() {
+            if pts[i][j] < constraints.min_height
+                || (constraints.visible && !mask[i][j])
+                || (constraints.exclude_border && (i == 0 || j == 0 || i == rows - 1 || j == cols - 1)) {
+                continue;
+            }
+            candidates.push((score, (i, j)));
+        }
+    }
+    let order = |a: &(usize, (usize, usize)), b: &(usize, (usize, usize))| b.0.cmp(&a.0).then(a.1.cmp(&b.1));
+    if count < candidates.len() {
+        candidates.select_nth_unstable_by(count, order);
+        candidates.truncate(count);
+    }
+    candidates.sort_unstable_by(order);
+    candidates.into_iter().map(|(score, point)| Site{
+        point,
+        height: pts[point.0][point.1],
+        score,
+        distances: DIRECTIONS.map(|dir| viewing_distance(dir, point, pts)),
+    }).collect()
 }
 
-fn iters(dir: Dir, (i, j): (usize, usize), pts: &Vec<Vec<u8>>) -> Box<dyn Iterator<Item=(usize, usize)>> {
-    if dir == Dir::Up {
-        return Box::new((0..i).rev().map(move |n| (n, j)));
-    } else if dir == Dir::Down {
-        return Box::new(((i+1)..pts.len()).map(move |n| (n, j)));
-    } else if dir == Dir::Left {
-        return Box::new((0..j).rev().map(move |m| (i, m)));
-    } else {
-        return Box::new(((j+1)..pts[i].len()).map(move |m| (i, m)));
+/// one character per tree, shaded by scenic score on a log scale
+fn heatmap(scores: &[Vec<usize>]) -> String {
+    let max = scores.iter().flatten().max().copied().unwrap_or(0);
+    let scale = ((max + 1) as f64).ln();
+    let mut ret = String::new();
+    for row in scores {
+        for &score in row {
+            let shade = if score == 0 {
+                0
+            } else {
+                1 + (((score + 1) as f64).ln() / scale * (SHADES.len() - 2) as f64) as usize
+            };
+            ret.push(SHADES[shade.min(SHADES.len() - 1)]);
+        }
+        ret.push('\n');
     }
+    ret
+}
+
+fn iters(dir: Dir, (i, j): (usize, usize), pts: &[Vec<u8>]) -> Box<dyn Iterator<Item=(usize, usize)>> {
+    match dir {
+        Dir::Up => Box::new((0..i).rev().map(move |n| (n, j))),
+        Dir::Down => Box::new(((i+1)..pts.len()).map(move |n| (n, j))),
+        Dir::Left => Box::new((0..j).rev().map(move |m| (i, m))),
+        Dir::Right => Box::new(((j+1)..pts[i].len()).map(move |m| (i, m))),
+    }
+}
+
+#[cfg(test)]
+fn scenic_score((i, j): (usize, usize), pts: &[Vec<u8>]) -> usize {
+    DIRECTIONS.iter().map(|&dir|
+    viewing_distance(dir, (i, j), pts)).product()
 }
 
-fn viewing_distance(dir: Dir, (i, j): (usize, usize), pts: &Vec<Vec<u8>>) -> usize {
+fn viewing_distance(dir: Dir, (i, j): (usize, usize), pts: &[Vec<u8>]) -> usize {
     let height = pts[i][j];
     let mut distance = 0;
     for (m, n) in iters(dir, (i, j), pts) {
@@ -79,7 +231,8 @@ fn viewing_distance(dir: Dir, (i, j): (usize, usize), pts: &Vec<Vec<u8>>) -> usi
     distance
 }
 
-fn visible_from(dir: Dir, pt: &(usize, usize), pts: &Vec<Vec<u8>>) -> bool {
+#[cfg(test)]
+fn visible_from(dir: Dir, pt: &(usize, usize), pts: &[Vec<u8>]) -> bool {
     let i = pt.0;
     let j = pt.1;
     let height = pts[i][j];
@@ -133,34 +286,84 @@ mod tests {
 
     #[test]
     fn invisible_from_left() {
-        let result = visible_from(Dir::Left, &(0, 1), &vec![vec![2, 1]]);
+        let result = visible_from(Dir::Left, &(0, 1), &[vec![2, 1]]);
         assert!(!result);
     }
 
     #[test]
     fn invisible_from_right() {
-        let result = visible_from(Dir::Right, &(0, 0), &vec![vec![1, 2]]);
+        let result = visible_from(Dir::Right, &(0, 0), &[vec![1, 2]]);
         assert!(!result);
     }
 
     #[test]
     fn invisible_from_top() {
-        let result = visible_from(Dir::Up, &(1, 0), &vec![vec![2], vec![1]]);
+        let result = visible_from(Dir::Up, &(1, 0), &[vec![2], vec![1]]);
         assert!(!result);
     }
 
     #[test]
     fn invisible_from_bottom() {
-        let result = visible_from(Dir::Down, &(0, 0), &vec![vec![1], vec![2]]);
+        let result = visible_from(Dir::Down, &(0, 0), &[vec![1], vec![2]]);
         assert!(!result);
     }
 
     #[test]
     fn outside_visible() {
-        let result = visible_from(Dir::Down, &(0, 0), &vec![vec![1, 1, 1], vec![1, 1, 1]]);
+        let result = visible_from(Dir::Down, &(0, 0), &[vec![1, 1, 1], vec![1, 1, 1]]);
         assert!(!result);
     }
 
+    /// a pseudo-random forest, so the sweeps can be checked against the
+    /// tree-by-tree walks
+    fn generated_forest(rows: usize, cols: usize, seed: u64) -> Vec<Vec<u8>> {
+        let mut state = seed;
+        (0..rows).map(|_| (0..cols).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 33) % 10) as u8
+        }).collect()).collect()
+    }
+
+    #[test]
+    fn sweeps_match_walks() {
+        for (rows, cols, seed) in [(1, 1, 1), (1, 7, 2), (9, 1, 3), (23, 31, 4), (40, 40, 5)] {
+            let pts = generated_forest(rows, cols, seed);
+            let mask = visibility_mask(&pts);
+            let scores = super::scenic_scores(&pts);
+            for i in 0..rows {
+                for j in 0..cols {
+                    let walked = DIRECTIONS.iter().any(|&dir| visible_from(dir, &(i, j), &pts));
+                    assert_eq!(mask[i][j], walked, "visibility at {:?}", (i, j));
+                    assert_eq!(scores[i][j], scenic_score((i, j), &pts), "score at {:?}", (i, j));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn example_best_sites() {
+        let pts = string_to_points(include_str!("../example.txt"));
+        let sites = best_sites(&pts, 2, &Constraints::default());
+        assert_eq!(sites[0], Site{point: (3, 2), height: 5, score: 8, distances: [2, 1, 2, 2]});
+        assert_eq!(sites[1], Site{point: (2, 1), height: 5, score: 6, distances: [1, 2, 1, 3]});
+        let sites = best_sites(&pts, 100, &Constraints{min_height: 6, exclude_border: true, ..Default::default()});
+        assert_eq!(sites, vec![]);
+        let sites = best_sites(&pts, 100, &Constraints{visible: true, exclude_border: true, ..Default::default()});
+        assert_eq!(sites.len(), 5);
+    }
+
+    #[test]
+    fn heatmap_shades() {
+        assert_eq!(heatmap(&[vec![0, 1, 8], vec![3, 0, 0]]), " -@\n*  \n");
+    }
+
+    #[test]
+    fn example_scenic_score() {
+        let pts = string_to_points(include_str!("../example.txt"));
+        assert_eq!(super::scenic_scores(&pts)[3][2], 8);
+        assert_eq!(max_scenic_score(&pts), 8);
+    }
+
     #[test]
     fn scenic_scores() {
         let pts = vec![
@@ -1,64 +1,282 @@
 use std::io;
 use std::io::Read;
-use std::collections::HashSet;
-// break down the instruction list (R 4 -> R R R R)
-// track the head through all the instructions
-// work out how the tail moves for each head movement
-// track the tail through all the instructions
-// number of unique locations the tail's been through
-
-// head position (x,y)
-// tail position (x,y)
-// tail locations [(x1,y1), (x2,y2),...]
-fn main() {
-    let mut buf = String::new();
-    io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
-    let mut inst_list = vec![];
-    for line in buf.lines() {
-        // R 4
-        let mut res = line.split(" ");
-        let inst = res.next().unwrap().chars().next().unwrap(); // R
-        let count = res.next().unwrap().parse::<usize>().unwrap(); // "4"
-        for _ in 0..count {
-            inst_list.push(inst.clone()); // ['R', 'R', 'R', 'R']
+use std::collections::{HashMap, HashSet};
+// a rope is a list of knots, the first being the head
+// each step moves the head one square, then each knot follows the one in front
+// every knot remembers each location it's been through
+
+type Point = (i64, i64);
+/// how a knot moves to catch up with the knot in front of it
+type FollowRule = fn(&mut Point, &Point);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Direction {
+    R,
+    U,
+    L,
+    D,
+    UL,
+    UR,
+    DL,
+    DR,
+}
+
+impl Direction {
+    fn delta(&self) -> Point {
+        match self {
+            Direction::R => (1, 0),
+            Direction::U => (0, 1),
+            Direction::L => (-1, 0),
+            Direction::D => (0, -1),
+            Direction::UL => (-1, 1),
+            Direction::UR => (1, 1),
+            Direction::DL => (-1, -1),
+            Direction::DR => (1, -1),
+        }
+    }
+}
+
+impl TryFrom<&str> for Direction {
+    type Error = ();
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "R" => Ok(Direction::R),
+            "U" => Ok(Direction::U),
+            "L" => Ok(Direction::L),
+            "D" => Ok(Direction::D),
+            "UL" => Ok(Direction::UL),
+            "UR" => Ok(Direction::UR),
+            "DL" => Ok(Direction::DL),
+            "DR" => Ok(Direction::DR),
+            _ => Err(()),
         }
     }
-    let mut head = (0, 0);
-    let mut tail = (0, 0);
-    let mut multitail = vec![];
-    for _ in 0..9 {
-        multitail.push((0, 0));
+}
+
+/// a straight run of cells, stored as the class of line it lies on, which
+/// line of that class, and the inclusive range of positions along it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Line {
+    Horizontal(i64),
+    Vertical(i64),
+    Diagonal(i64),
+    AntiDiagonal(i64),
+}
+
+impl Line {
+    /// the line through `point` with direction `delta`, and the point's position along it
+    fn through(point: Point, delta: Point) -> (Self, i64) {
+        match delta {
+            (_, 0) => (Line::Horizontal(point.1), point.0),
+            (0, _) => (Line::Vertical(point.0), point.1),
+            (dx, dy) if dx == dy => (Line::Diagonal(point.0 - point.1), point.0),
+            _ => (Line::AntiDiagonal(point.0 + point.1), point.0),
+        }
     }
-    let mut tail_locations = HashSet::new();
-    let mut multitail_locations = HashSet::new();
-    tail_locations.insert(tail.clone());
-    for inst in inst_list {
-        process_instruction(&mut head, inst);
-        process_tail_catch_up(&mut tail, &head);
-        process_multitail(&mut multitail, &head);
-        tail_locations.insert(tail.clone());
-        multitail_locations.insert(multitail[8].clone());
+
+    fn lines_through(point: Point) -> [(Self, i64); 4] {
+        [(1, 0), (0, 1), (1, 1), (1, -1)].map(|delta| Line::through(point, delta))
     }
-    println!("The second knot has been at {} locations", tail_locations.len());
-    println!("The final knot has been {} locations", multitail_locations.len());
+
+    fn point(&self, position: i64) -> Point {
+        match *self {
+            Line::Horizontal(y) => (position, y),
+            Line::Vertical(x) => (x, position),
+            Line::Diagonal(d) => (position, position - d),
+            Line::AntiDiagonal(a) => (position, a - position),
+        }
+    }
+
+    /// the position along self at which it crosses other, if that's a whole cell
+    fn crossing(&self, other: &Line) -> Option<i64> {
+        let point = match (*self, *other) {
+            (Line::Horizontal(y), Line::Vertical(x)) => (x, y),
+            (Line::Horizontal(y), Line::Diagonal(d)) => (y + d, y),
+            (Line::Horizontal(y), Line::AntiDiagonal(a)) => (a - y, y),
+            (Line::Vertical(x), Line::Diagonal(d)) => (x, x - d),
+            (Line::Vertical(x), Line::AntiDiagonal(a)) => (x, a - x),
+            (Line::Diagonal(d), Line::AntiDiagonal(a)) if (a + d) % 2 == 0 => ((a + d) / 2, (a - d) / 2),
+            (Line::Diagonal(_), Line::AntiDiagonal(_)) => return None,
+            _ if std::mem::discriminant(self) == std::mem::discriminant(other) => return None,
+            _ => return other.crossing(self).map(|p| Line::through(other.point(p), self.delta()).1),
+        };
+        Some(Line::through(point, self.delta()).1)
+    }
+
+    fn delta(&self) -> Point {
+        match self {
+            Line::Horizontal(_) => (1, 0),
+            Line::Vertical(_) => (0, 1),
+            Line::Diagonal(_) => (1, 1),
+            Line::AntiDiagonal(_) => (1, -1),
+        }
+    }
+}
+
+/// the cells a knot has visited: single steps are kept as points, and the
+/// long straight runs skipped over in a steady state are kept as line segments
+#[derive(Default)]
+struct Visits {
+    points: HashSet<Point>,
+    runs: Vec<(Line, i64, i64)>,
+}
+
+impl Visits {
+    fn insert(&mut self, point: Point) {
+        self.points.insert(point);
+    }
+
+    /// the `count` cells after `start`, moving by `delta` each time
+    fn insert_run(&mut self, start: Point, delta: Point, count: i64) {
+        let (line, position) = Line::through(start, delta);
+        // positions run along x, except on vertical lines
+        let step = if delta.0 != 0 { delta.0 } else { delta.1 };
+        let end = position + step * count;
+        self.runs.push((line, (position + step).min(end), (position + step).max(end)));
+    }
+
+    /// merged, sorted ranges of positions along each line
+    fn merged_runs(&self) -> HashMap<Line, Vec<(i64, i64)>> {
+        let mut runs = self.runs.clone();
+        runs.sort();
+        let mut ret: HashMap<Line, Vec<(i64, i64)>> = HashMap::new();
+        for (line, from, to) in runs {
+            let ranges = ret.entry(line).or_default();
+            match ranges.last_mut() {
+                Some(last) if from <= last.1 + 1 => last.1 = last.1.max(to),
+                _ => ranges.push((from, to)),
+            }
+        }
+        ret
+    }
+
+    fn len(&self) -> usize {
+        let lines = self.merged_runs();
+        let mut total: i64 = lines.values().flatten().map(|(from, to)| to - from + 1).sum();
+        // cells where runs on different classes of line cross were counted more than once
+        let mut crossings: HashMap<Point, i64> = HashMap::new();
+        for (line, ranges) in &lines {
+            for (other, other_ranges) in &lines {
+                if line >= other {
+                    continue;
+                }
+                let (Some(p), Some(q)) = (line.crossing(other), other.crossing(line)) else {
+                    continue;
+                };
+                let within = |ranges: &Vec<(i64, i64)>, p| ranges.iter().any(|&(from, to)| from <= p && p <= to);
+                if within(ranges, p) && within(other_ranges, q) {
+                    *crossings.entry(line.point(p)).or_default() += 1;
+                }
+            }
+        }
+        // n pairwise crossings at one cell means k lines with k(k-1)/2 = n
+        for pairs in crossings.values() {
+            let k = (1..=4).find(|k| k * (k - 1) / 2 == *pairs).unwrap();
+            total -= k - 1;
+        }
+        let covered = |point: &Point| Line::lines_through(*point).iter().any(|(line, p)| {
+            lines.get(line).is_some_and(|ranges| {
+                let i = ranges.partition_point(|&(_, to)| to < *p);
+                i < ranges.len() && ranges[i].0 <= *p
+            })
+        });
+        total as usize + self.points.iter().filter(|p| !covered(p)).count()
+    }
+}
+
+struct Rope {
+    knots: Vec<Point>,
+    previous: Vec<Point>,
+    visited: Vec<Visits>,
+    follow: FollowRule,
 }
 
-fn process_instruction(head: &mut (i32, i32), inst: char) {
-    if inst == 'R' {
-        head.0 += 1;
+impl Rope {
+    fn new(length: usize) -> Self {
+        Self::with_rule(length, process_tail_catch_up)
+    }
+
+    fn with_rule(length: usize, follow: FollowRule) -> Self {
+        let knots = vec![(0, 0); length];
+        let visited = knots.iter().map(|&k| {
+            let mut visits = Visits::default();
+            visits.insert(k);
+            visits
+        }).collect();
+        Self{previous: knots.clone(), knots, visited, follow}
     }
-    if inst == 'U' {
-        head.1 += 1;
+
+    /// move the head one square and return where every knot ends up
+    fn step(&mut self, dir: Direction) -> &[Point] {
+        self.previous.clone_from(&self.knots);
+        let (head, tail) = self.knots.split_first_mut().unwrap();
+        process_instruction(head, dir);
+        process_multitail(tail, head, self.follow);
+        for (knot, visited) in self.knots.iter().zip(self.visited.iter_mut()) {
+            visited.insert(*knot);
+        }
+        &self.knots
     }
-    if inst == 'L' {
-        head.0 -= 1;
+
+    /// Once every knot moves exactly as the head does, the rope is straight
+    /// and each further step is the same translation, so the rest of the move
+    /// is skipped over as one run per knot.
+    /// This relies on the follow rule only depending on relative positions.
+    fn apply(&mut self, dir: Direction, count: usize) {
+        let delta = dir.delta();
+        for done in 1..=count {
+            self.step(dir);
+            let steady = self.knots.iter().zip(&self.previous)
+                .all(|(k, p)| (k.0 - p.0, k.1 - p.1) == delta);
+            if steady {
+                let remaining = (count - done) as i64;
+                if remaining > 0 {
+                    for (knot, visited) in self.knots.iter_mut().zip(self.visited.iter_mut()) {
+                        visited.insert_run(*knot, delta, remaining);
+                        knot.0 += delta.0 * remaining;
+                        knot.1 += delta.1 * remaining;
+                    }
+                }
+                return;
+            }
+        }
+    }
+
+    fn visited(&self, knot: usize) -> &Visits {
+        &self.visited[knot]
+    }
+}
+
+fn main() {
+    let mut buf = String::new();
+    io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
+    let mut rope = Rope::new(10);
+    for (dir, count) in parse_moves(&buf) {
+        rope.apply(dir, count);
     }
-    if inst == 'D' {
-        head.1 -= 1;
+    println!("The second knot has been at {} locations", rope.visited(1).len());
+    println!("The final knot has been {} locations", rope.visited(9).len());
+}
+
+fn parse_moves(input: &str) -> Vec<(Direction, usize)> {
+    let mut ret = vec![];
+    for line in input.lines() {
+        // R 4
+        let mut res = line.split(' ');
+        let dir = Direction::try_from(res.next().unwrap()).unwrap(); // R
+        let count = res.next().unwrap().parse::<usize>().unwrap(); // "4"
+        ret.push((dir, count));
     }
+    ret
 }
 
-fn process_tail_catch_up(tail: &mut (i32, i32), head: &(i32, i32)) {
+fn process_instruction(head: &mut Point, inst: Direction) {
+    let (dx, dy) = inst.delta();
+    head.0 += dx;
+    head.1 += dy;
+}
+
+fn process_tail_catch_up(tail: &mut Point, head: &Point) {
     if ((head.0 - tail.0).abs() > 1 && (head.1 - tail.1).abs() > 0) ||
         ((head.0 - tail.0).abs() > 0 && (head.1 - tail.1).abs() > 1)
     {
@@ -82,10 +300,10 @@ fn process_tail_catch_up(tail: &mut (i32, i32), head: &(i32, i32)) {
     }
 }
 
-fn process_multitail(tail: &mut Vec<(i32, i32)>, head: &(i32, i32)) {
-    let mut prev = head.clone();
+fn process_multitail(tail: &mut [Point], head: &Point, follow: FollowRule) {
+    let mut prev = *head;
     for segment in tail {
-        process_tail_catch_up(segment, &prev);
+        follow(segment, &prev);
         prev = *segment;
     }
 }
@@ -96,7 +314,7 @@ mod tests {
 
     #[test]
     fn right_instruction_increments_by_1() {
-        let instruction = 'R';
+        let instruction = Direction::R;
         let mut head = (4, 7);
         process_instruction(&mut head, instruction);
         assert_eq!(head.0, 5);
@@ -105,11 +323,11 @@ mod tests {
     #[test]
     fn other_instructions_behave_as_expected() {
         let mut head = (4, 7);
-        process_instruction(&mut head, 'D');
+        process_instruction(&mut head, Direction::D);
         assert_eq!(head.1, 6);
-        process_instruction(&mut head, 'L');
+        process_instruction(&mut head, Direction::L);
         assert_eq!(head.0, 3);
-        process_instruction(&mut head, 'U');
+        process_instruction(&mut head, Direction::U);
         assert_eq!(head.1, 7);
     }
 
@@ -160,9 +378,95 @@ mod tests {
     fn multitail_moves() {
         let head = (3, 2);
         let mut tails = vec![(1, 1), (0, 0)];
-        process_multitail(&mut tails, &head);
+        process_multitail(&mut tails, &head, process_tail_catch_up);
         assert_eq!(head, (3, 2));
         assert_eq!(tails[0], (2, 2));
         assert_eq!(tails[1], (1, 1));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn examples() {
+        for (input, length, expected) in [
+            (include_str!("../example.txt"), 2, 13),
+            (include_str!("../example.txt"), 10, 1),
+            (include_str!("../example2.txt"), 10, 36),
+        ] {
+            let mut rope = Rope::new(length);
+            for (dir, count) in parse_moves(input) {
+                rope.apply(dir, count);
+            }
+            assert_eq!(rope.visited(length - 1).len(), expected);
+        }
+    }
+
+    #[test]
+    fn diagonal_head_moves() {
+        let mut rope = Rope::new(3);
+        assert_eq!(rope.step(Direction::UR), &[(1, 1), (0, 0), (0, 0)]);
+        assert_eq!(rope.step(Direction::UR), &[(2, 2), (1, 1), (0, 0)]);
+        assert_eq!(rope.step(Direction::DR), &[(3, 1), (2, 1), (1, 1)]);
+        assert_eq!(rope.step(Direction::DL), &[(2, 0), (2, 1), (1, 1)]);
+        assert_eq!(rope.step(Direction::UL), &[(1, 1), (2, 1), (1, 1)]);
+        assert_eq!(rope.visited(1).len(), 3);
+    }
+
+    #[test]
+    fn skipping_matches_stepping() {
+        let mut state: u64 = 9;
+        let mut random = |n: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) % n
+        };
+        let directions = [Direction::R, Direction::U, Direction::L, Direction::D,
+            Direction::UL, Direction::UR, Direction::DL, Direction::DR];
+        let moves: Vec<(Direction, usize)> = (0..300)
+            .map(|_| (directions[random(8) as usize], 1 + random(40) as usize)).collect();
+        let mut rope = Rope::new(10);
+        let mut stepped = Rope::new(10);
+        let mut seen = vec![HashSet::from([(0, 0)]); 10];
+        for &(dir, count) in &moves {
+            rope.apply(dir, count);
+            for _ in 0..count {
+                for (knot, seen) in stepped.step(dir).iter().zip(seen.iter_mut()) {
+                    seen.insert(*knot);
+                }
+            }
+        }
+        assert_eq!(rope.knots, stepped.knots);
+        for (knot, seen) in seen.iter().enumerate() {
+            assert_eq!(rope.visited(knot).len(), seen.len(), "knot {knot}");
+        }
+    }
+
+    #[test]
+    fn huge_moves() {
+        let mut rope = Rope::new(10);
+        rope.apply(Direction::R, 1_000_000_000);
+        assert_eq!(rope.knots[9], (1_000_000_000 - 9, 0));
+        assert_eq!(rope.visited(9).len(), 1_000_000_000 - 8);
+        rope.apply(Direction::U, 1_000_000_000);
+        rope.apply(Direction::DL, 2_000_000_000);
+        assert_eq!(rope.knots[0], (-1_000_000_000, -1_000_000_000));
+        // the diagonal run back crosses the first run at (1, 0)
+        let mut stepped = Rope::new(2);
+        stepped.apply(Direction::R, 1_000_000);
+        stepped.apply(Direction::U, 1_000_000);
+        stepped.apply(Direction::DL, 2_000_000);
+        assert_eq!(stepped.visited(1).len(), 1_000_000 + 999_999 + 1_999_999 - 1);
+    }
+
+    // the knot moves onto the square its leader just left
+    fn drag(tail: &mut Point, head: &Point) {
+        if (head.0 - tail.0).abs() > 1 || (head.1 - tail.1).abs() > 1 {
+            *tail = (head.0 - (head.0 - tail.0).signum(), head.1 - (head.1 - tail.1).signum());
+        }
+    }
+
+    #[test]
+    fn custom_follow_rule() {
+        let mut rope = Rope::with_rule(3, drag);
+        rope.apply(Direction::R, 3);
+        assert_eq!(rope.knots, vec![(3, 0), (2, 0), (1, 0)]);
+        assert_eq!(rope.visited(2).len(), 2);
+    }
+}
@@ -1,50 +1,672 @@
 use std::io;
 use std::io::Read;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
 
-fn main() {
-    let mut latest = 1;
-    let mut reg_history = vec![1];
-    let mut buf = String::new();
-    io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
-    for line in buf.lines() {
-        process_instruction(line.to_string(), &mut reg_history, &mut latest);
-    }
-    let strength_indexes: [usize; 6] = [20, 60, 100, 140, 180, 220];
-    let sum: i32 = strength_indexes.iter().map(|n| signal_strength(*n, &reg_history)).sum();
-    println!("Sum of signal strengths is {}", sum);
-    let mut output = String::new();
-    for (n, v) in reg_history.iter().enumerate() {
-        let m = (n % 40) as i32;
-        if (m-v).abs() < 2 {
-            output.push('#');
+const REGISTER_NAMES: [char; 4] = ['w', 'x', 'y', 'z'];
+const X: usize = 1;
+const STRENGTH_CYCLES: [usize; 6] = [20, 60, 100, 140, 180, 220];
+const CRT_WIDTH: usize = 40;
+const CRT_HEIGHT: usize = 6;
+/// X values beyond these never light a pixel
+const X_RANGE: (i32, i32) = (-2, CRT_WIDTH as i32 + 1);
+const MAX_MACRO_DEPTH: usize = 32;
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+/// the block capitals drawn on the CRT, each followed by a blank column
+const GLYPHS: [(char, [&str; GLYPH_HEIGHT]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+    (' ', ["....", "....", "....", "....", "....", "...."]),
+];
+
+type Registers = [i32; REGISTER_NAMES.len()];
+
+#[derive(Debug, PartialEq)]
+enum Operand {
+    Register,
+    Value,
+}
+
+/// an entry in the instruction set: the effect is applied at the end of the
+/// instruction's last cycle
+struct InstructionSpec {
+    name: &'static str,
+    operands: &'static [Operand],
+    cycles: usize,
+    effect: fn(&mut Registers, &[i32]),
+}
+
+const INSTRUCTION_SET: [InstructionSpec; 4] = [
+    InstructionSpec{name: "noop", operands: &[], cycles: 1, effect: |_, _| {}},
+    InstructionSpec{name: "addx", operands: &[Operand::Value], cycles: 2, effect: |r, a| r[X] += a[0]},
+    InstructionSpec{name: "add", operands: &[Operand::Register, Operand::Value], cycles: 2,
+        effect: |r, a| r[a[0] as usize] += a[1]},
+    InstructionSpec{name: "set", operands: &[Operand::Register, Operand::Value], cycles: 1,
+        effect: |r, a| r[a[0] as usize] = a[1]},
+];
+
+/// register operands are stored as their index into Registers
+struct Instruction {
+    spec: &'static InstructionSpec,
+    args: Vec<i32>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    UnknownInstruction(String),
+    WrongOperandCount{expected: usize, found: usize},
+    BadOperand(String),
+}
+
+impl TryFrom<&str> for Instruction {
+    type Error = ParseError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut split = value.split_whitespace();
+        let name = split.next().unwrap_or_default();
+        let spec = INSTRUCTION_SET.iter().find(|spec| spec.name == name)
+            .ok_or_else(|| ParseError::UnknownInstruction(name.to_string()))?;
+        let words: Vec<&str> = split.collect();
+        if words.len() != spec.operands.len() {
+            return Err(ParseError::WrongOperandCount{expected: spec.operands.len(), found: words.len()});
+        }
+        let mut args = vec![];
+        for (word, operand) in words.iter().zip(spec.operands) {
+            let arg = match operand {
+                Operand::Register => REGISTER_NAMES.iter().position(|&r| word.len() == 1 && word.starts_with(r))
+                    .map(|ix| ix as i32),
+                Operand::Value => word.parse::<i32>().ok(),
+            };
+            args.push(arg.ok_or_else(|| ParseError::BadOperand(word.to_string()))?);
+        }
+        Ok(Self{spec, args})
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.spec.name)?;
+        for (arg, operand) in self.args.iter().zip(self.spec.operands) {
+            match operand {
+                Operand::Register => write!(f, " {}", REGISTER_NAMES[*arg as usize])?,
+                Operand::Value => write!(f, " {arg}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// something that watches the CPU as it runs
+trait Plugin {
+    /// called during each cycle, before any instruction finishing that cycle takes effect
+    fn during(&mut self, _cycle: usize, _registers: &Registers) {}
+    /// called at the end of each cycle, after any instruction finishing that cycle
+    fn after(&mut self, _cycle: usize, _registers: &Registers) {}
+}
+
+struct Cpu {
+    registers: Registers,
+    cycle: usize,
+}
+
+impl Cpu {
+    fn new() -> Self {
+        let mut registers = [0; REGISTER_NAMES.len()];
+        registers[X] = 1;
+        Self{registers, cycle: 0}
+    }
+
+    fn run(&mut self, program: &[Instruction], plugins: &mut [&mut dyn Plugin]) {
+        for inst in program {
+            self.process_instruction(inst, plugins);
+        }
+    }
+
+    fn process_instruction(&mut self, inst: &Instruction, plugins: &mut [&mut dyn Plugin]) {
+        for n in 1..=inst.spec.cycles {
+            self.cycle += 1;
+            for plugin in plugins.iter_mut() {
+                plugin.during(self.cycle, &self.registers);
+            }
+            if n == inst.spec.cycles {
+                (inst.spec.effect)(&mut self.registers, &inst.args);
+            }
+            for plugin in plugins.iter_mut() {
+                plugin.after(self.cycle, &self.registers);
+            }
+        }
+    }
+}
+
+/// sums cycle * X during each of the given cycles
+struct SignalStrength {
+    cycles: Vec<usize>,
+    sum: i32,
+}
+
+impl Plugin for SignalStrength {
+    fn during(&mut self, cycle: usize, registers: &Registers) {
+        if self.cycles.contains(&cycle) {
+            self.sum += cycle as i32 * registers[X];
+        }
+    }
+}
+
+/// draws one pixel per cycle, lit when the 3-wide sprite centred on X covers it
+#[derive(Default)]
+struct Crt {
+    output: String,
+}
+
+impl Plugin for Crt {
+    fn during(&mut self, cycle: usize, registers: &Registers) {
+        let m = ((cycle - 1) % CRT_WIDTH) as i32;
+        if (m - registers[X]).abs() < 2 {
+            self.output.push('#');
         } else {
-            output.push('.');
+            self.output.push('.');
         }
-        if m == 39 {
-            output.push('\n');
+        if m as usize == CRT_WIDTH - 1 {
+            self.output.push('\n');
         }
     }
-    print!("{}", output);
 }
 
-fn process_instruction(inst: String, reg_history: &mut Vec<i32>, latest: &mut i32) {
-    let mut split = inst.split(" ");
-    match split.next() {
-        Some("noop") => {
-            reg_history.push(latest.clone());
-        },
-        Some("addx") => {
-            if let Some(val) = split.next() {
-                reg_history.push(latest.clone());
-                *latest += val.parse::<i32>().unwrap();
-                reg_history.push(latest.clone());
+/// a glyph that isn't in GLYPHS: the letter's index and its pixels
+#[derive(Debug, PartialEq)]
+struct UnknownGlyph {
+    index: usize,
+    column: usize,
+    rows: Vec<String>,
+}
+
+/// read the letters drawn on the CRT, reporting every glyph that can't be read
+fn decode(image: &str) -> Result<String, Vec<UnknownGlyph>> {
+    let rows: Vec<&str> = image.lines().collect();
+    let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let mut text = String::new();
+    let mut unknown = vec![];
+    for (index, column) in (0..width).step_by(GLYPH_WIDTH + 1).enumerate() {
+        let glyph: Vec<String> = (0..GLYPH_HEIGHT).map(|r| {
+            let row = rows.get(r).copied().unwrap_or_default();
+            row.get(column..(column + GLYPH_WIDTH).min(row.len())).unwrap_or_default().to_string()
+        }).collect();
+        match GLYPHS.iter().find(|(_, pixels)| pixels.iter().eq(glyph.iter())) {
+            Some((letter, _)) => text.push(*letter),
+            None => unknown.push(UnknownGlyph{index, column, rows: glyph}),
+        }
+    }
+    if unknown.is_empty() {
+        Ok(text)
+    } else {
+        Err(unknown)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum AssembleError {
+    UnknownInstruction(String),
+    UnknownSymbol(String),
+    WrongOperandCount{expected: usize, found: usize},
+    DuplicateLabel(String),
+    UnterminatedMacro(String),
+    MacroTooDeep(String),
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<(usize, String)>,
+}
+
+enum Item {
+    Label(String),
+    Noop,
+    Addx(String),
+}
+
+/// Assemble source into noop/addx lines. Source may contain `; comments`,
+/// `label:` markers, which stand for the cycle the next instruction starts on,
+/// and macros:
+///     .macro name param...
+///         body, with params substituted (also as `-param`)
+///     .endm
+/// Errors carry the 1-based line number.
+fn assemble(source: &str) -> Result<Vec<String>, (usize, AssembleError)> {
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    let mut lines = vec![];
+    let mut current: Option<(usize, String, Macro)> = None;
+    for (n, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l)) {
+        let line = line.split(';').next().unwrap().trim();
+        let mut words = line.split_whitespace();
+        match (words.next(), current.as_mut()) {
+            (None, _) => {},
+            (Some(".endm"), Some(_)) => {
+                let (_, name, definition) = current.take().unwrap();
+                macros.insert(name, definition);
+            },
+            (Some(_), Some((_, _, definition))) => definition.body.push((n, line.to_string())),
+            (Some(".macro"), None) => {
+                let name = words.next().unwrap_or_default().to_string();
+                let params = words.map(String::from).collect();
+                current = Some((n, name, Macro{params, body: vec![]}));
+            },
+            (Some(_), None) => lines.push((n, line.to_string())),
+        }
+    }
+    if let Some((n, name, _)) = current {
+        return Err((n, AssembleError::UnterminatedMacro(name)));
+    }
+    let mut items = vec![];
+    expand(&lines, &macros, &HashMap::new(), 0, &mut items)?;
+    let mut labels = HashMap::new();
+    let mut cycle = 1;
+    for (n, item) in &items {
+        match item {
+            Item::Label(name) => if labels.insert(name.clone(), cycle).is_some() {
+                return Err((*n, AssembleError::DuplicateLabel(name.clone())));
+            },
+            Item::Noop => cycle += 1,
+            Item::Addx(_) => cycle += 2,
+        }
+    }
+    let mut ret = vec![];
+    for (n, item) in items {
+        match item {
+            Item::Label(_) => {},
+            Item::Noop => ret.push("noop".to_string()),
+            Item::Addx(operand) => {
+                let value = resolve(&operand, &labels).ok_or((n, AssembleError::UnknownSymbol(operand)))?;
+                ret.push(format!("addx {value}"));
+            },
+        }
+    }
+    Ok(ret)
+}
+
+fn expand(
+    lines: &[(usize, String)],
+    macros: &HashMap<String, Macro>,
+    args: &HashMap<&str, &str>,
+    depth: usize,
+    items: &mut Vec<(usize, Item)>,
+) -> Result<(), (usize, AssembleError)> {
+    for (n, line) in lines {
+        let mut words: Vec<String> = line.split_whitespace().map(|w| {
+            let (sign, name) = w.strip_prefix('-').map_or(("", w), |name| ("-", name));
+            args.get(name).map_or(w.to_string(), |arg| format!("{sign}{arg}"))
+        }).collect();
+        if let Some(label) = words.first().and_then(|w| w.strip_suffix(':')) {
+            items.push((*n, Item::Label(label.to_string())));
+            words.remove(0);
+        }
+        let Some(name) = words.first() else {
+            continue;
+        };
+        let operands = &words[1..];
+        let expect = |expected: usize| if operands.len() == expected {
+            Ok(())
+        } else {
+            Err((*n, AssembleError::WrongOperandCount{expected, found: operands.len()}))
+        };
+        match name.as_str() {
+            "noop" => {
+                expect(0)?;
+                items.push((*n, Item::Noop));
+            },
+            "addx" => {
+                expect(1)?;
+                items.push((*n, Item::Addx(operands[0].clone())));
+            },
+            _ => {
+                let definition = macros.get(name)
+                    .ok_or_else(|| (*n, AssembleError::UnknownInstruction(name.clone())))?;
+                expect(definition.params.len())?;
+                if depth == MAX_MACRO_DEPTH {
+                    return Err((*n, AssembleError::MacroTooDeep(name.clone())));
+                }
+                let args = definition.params.iter().map(String::as_str)
+                    .zip(operands.iter().map(String::as_str)).collect();
+                expand(&definition.body, macros, &args, depth + 1, items)?;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// an integer, or a label, either possibly negated
+fn resolve(operand: &str, labels: &HashMap<String, usize>) -> Option<i32> {
+    let mut sign = 1;
+    let mut operand = operand;
+    while let Some(rest) = operand.strip_prefix('-') {
+        sign = -sign;
+        operand = rest;
+    }
+    let value = operand.parse::<i32>().ok().or_else(|| labels.get(operand).map(|&c| c as i32))?;
+    Some(sign * value)
+}
+
+/// list a program with the cycles each instruction takes and what it does to X
+fn disassemble(program: &[Instruction]) -> String {
+    let mut cpu = Cpu::new();
+    let mut ret = String::new();
+    for inst in program {
+        let (start, before) = (cpu.cycle + 1, cpu.registers[X]);
+        cpu.process_instruction(inst, &mut []);
+        let cycles = if cpu.cycle == start {
+            format!("cycle {start}")
+        } else {
+            format!("cycles {start}-{}", cpu.cycle)
+        };
+        let effect = if cpu.registers[X] == before {
+            format!("X={before}")
+        } else {
+            format!("X={before} -> {}", cpu.registers[X])
+        };
+        ret.push_str(&format!("{:<12}; {cycles}, {effect}\n", inst.to_string()));
+    }
+    ret
+}
+
+/// an index into the possible values of X, and whether it's been held for 1 or 2+ cycles
+type State = (usize, usize);
+
+#[derive(Debug, PartialEq)]
+enum SynthesisError {
+    BadImage,
+    /// no value of X can draw the image up to this (row, column)
+    Impossible(usize, usize),
+    VerificationFailed,
+}
+
+/// Find a program drawing the given 40x6 image, using as few addx as possible.
+/// X only changes at the end of an addx, so every change needs X to have
+/// held its value for at least the two cycles before.
+fn synthesise(image: &str) -> Result<Vec<String>, SynthesisError> {
+    let rows: Vec<&str> = image.lines().collect();
+    if rows.len() != CRT_HEIGHT || rows.iter().any(|r| r.len() != CRT_WIDTH || r.chars().any(|c| c != '#' && c != '.')) {
+        return Err(SynthesisError::BadImage);
+    }
+    let lit: Vec<bool> = rows.iter().flat_map(|r| r.chars().map(|c| c == '#')).collect();
+    let values: Vec<i32> = (X_RANGE.0..=X_RANGE.1).collect();
+    let draws = |cycle: usize, x: i32| (((cycle % CRT_WIDTH) as i32 - x).abs() < 2) == lit[cycle];
+    // best[x][held] = fewest addx to reach each state, with history recording
+    // the state each came from
+    let mut best: Vec<[Option<usize>; 2]> = vec![[None; 2]; values.len()];
+    let mut history: Vec<Vec<[Option<State>; 2]>> = vec![];
+    let start = values.iter().position(|&x| x == 1).unwrap();
+    if !draws(0, 1) {
+        return Err(SynthesisError::Impossible(0, 0));
+    }
+    best[start][0] = Some(0);
+    for cycle in 1..lit.len() {
+        let mut next: Vec<[Option<usize>; 2]> = vec![[None; 2]; values.len()];
+        let mut back = vec![[None; 2]; values.len()];
+        let mut offer = |x: usize, held: usize, cost: usize, from: State| {
+            if next[x][held].is_none_or(|c| cost < c) {
+                next[x][held] = Some(cost);
+                back[x][held] = Some(from);
             }
+        };
+        for (x, costs) in best.iter().enumerate() {
+            for (held, cost) in costs.iter().enumerate() {
+                let Some(cost) = *cost else { continue };
+                if draws(cycle, values[x]) {
+                    offer(x, 1, cost, (x, held));
+                }
+                if held == 1 {
+                    for (y, &value) in values.iter().enumerate() {
+                        if y != x && draws(cycle, value) {
+                            offer(y, 0, cost + 1, (x, held));
+                        }
+                    }
+                }
+            }
+        }
+        if next.iter().flatten().all(Option::is_none) {
+            return Err(SynthesisError::Impossible(cycle / CRT_WIDTH, cycle % CRT_WIDTH));
+        }
+        best = next;
+        history.push(back);
+    }
+    let mut state = (0..values.len()).flat_map(|x| [(x, 0), (x, 1)])
+        .filter(|&(x, held)| best[x][held].is_some())
+        .min_by_key(|&(x, held)| best[x][held]).unwrap();
+    let mut xs = vec![values[state.0]];
+    for back in history.iter().rev() {
+        state = back[state.0][state.1].unwrap();
+        xs.push(values[state.0]);
+    }
+    xs.reverse();
+    let mut program = vec![];
+    let mut run = 0;
+    for (cycle, &x) in xs.iter().enumerate() {
+        run += 1;
+        match xs.get(cycle + 1) {
+            Some(&next) if next != x => {
+                program.extend(vec!["noop".to_string(); run - 2]);
+                program.push(format!("addx {}", next - x));
+                run = 0;
+            },
+            _ => {},
+        }
+    }
+    program.extend(vec!["noop".to_string(); run]);
+    let instructions = parse_program(&program.join("\n")).map_err(|_| SynthesisError::VerificationFailed)?;
+    let mut crt = Crt::default();
+    Cpu::new().run(&instructions, &mut [&mut crt]);
+    if crt.output.lines().ne(rows.iter().copied()) {
+        return Err(SynthesisError::VerificationFailed);
+    }
+    Ok(program)
+}
+
+fn main() {
+    let mut buf = String::new();
+    io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+        ["assemble"] => match assemble(&buf) {
+            Ok(program) => program.iter().for_each(|line| println!("{line}")),
+            Err((n, e)) => println!("Error on line {n}: {e:?}"),
+        },
+        ["disassemble"] => print!("{}", disassemble(&parse_program(&buf)
+            .unwrap_or_else(|(n, e)| panic!("Error on line {n}: {e:?}")))),
+        ["synthesise"] => match synthesise(&buf) {
+            Ok(program) => program.iter().for_each(|line| println!("{line}")),
+            Err(e) => println!("Can't draw that image: {e:?}"),
         },
-        _ => unimplemented!(),
+        _ => run(&buf),
     }
 }
 
-fn signal_strength(ix: usize, reg_history: &Vec<i32>) -> i32 {
-    let ret = (ix as i32) * reg_history[ix - 1];
-    ret
+fn run(source: &str) {
+    let program = parse_program(source).unwrap_or_else(|(n, e)| panic!("Error on line {n}: {e:?}"));
+    let mut strength = SignalStrength{cycles: STRENGTH_CYCLES.to_vec(), sum: 0};
+    let mut crt = Crt::default();
+    Cpu::new().run(&program, &mut [&mut strength, &mut crt]);
+    println!("Sum of signal strengths is {}", strength.sum);
+    print!("{}", crt.output);
+    match decode(&crt.output) {
+        Ok(text) => println!("CRT letters: {}", text),
+        Err(unknown) => println!("CRT letters unreadable: unknown glyphs at columns {:?}",
+            unknown.iter().map(|g| g.column).collect::<Vec<usize>>()),
+    }
+}
+
+/// errors carry the 1-based line number
+fn parse_program(input: &str) -> Result<Vec<Instruction>, (usize, ParseError)> {
+    input.lines().enumerate()
+        .map(|(n, line)| Instruction::try_from(line).map_err(|e| (n + 1, e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_signal_strength() {
+        let program = parse_program(include_str!("../example.txt")).unwrap();
+        let mut strength = SignalStrength{cycles: STRENGTH_CYCLES.to_vec(), sum: 0};
+        Cpu::new().run(&program, &mut [&mut strength]);
+        assert_eq!(strength.sum, 13140);
+    }
+
+    #[test]
+    fn example_crt() {
+        let program = parse_program(include_str!("../example.txt")).unwrap();
+        let mut crt = Crt::default();
+        Cpu::new().run(&program, &mut [&mut crt]);
+        assert!(crt.output.starts_with("##..##..##..##..##..##..##..##..##..##..\n###...###"));
+        assert_eq!(crt.output.len(), 6 * (CRT_WIDTH + 1));
+    }
+
+    struct History(Vec<(usize, i32, i32)>);
+
+    impl Plugin for History {
+        fn during(&mut self, cycle: usize, registers: &Registers) {
+            self.0.push((cycle, registers[X], 0));
+        }
+        fn after(&mut self, _cycle: usize, registers: &Registers) {
+            self.0.last_mut().unwrap().2 = registers[X];
+        }
+    }
+
+    #[test]
+    fn small_program() {
+        let program = parse_program("noop\naddx 3\naddx -5\n").unwrap();
+        let mut history = History(vec![]);
+        Cpu::new().run(&program, &mut [&mut history]);
+        assert_eq!(history.0, vec![(1, 1, 1), (2, 1, 1), (3, 1, 4), (4, 4, 4), (5, 4, -1)]);
+    }
+
+    #[test]
+    fn other_registers() {
+        let program = parse_program("set y 7\nadd y -2\nadd w 3\n").unwrap();
+        let mut cpu = Cpu::new();
+        cpu.run(&program, &mut []);
+        assert_eq!(cpu.registers, [3, 1, 5, 0]);
+        assert_eq!(cpu.cycle, 5);
+    }
+
+    #[test]
+    fn decode_letters() {
+        let image = "\
+###...##..#..#..##..####.###..####.####.
+#..#.#..#.#.#..#..#.#....#..#.#.......#.
+###..#....##...#..#.###..#..#.###....#..
+#..#.#.##.#.#..####.#....###..#.....#...
+#..#.#..#.#.#..#..#.#....#.#..#....#....
+###...###.#..#.#..#.####.#..#.####.####.
+";
+        assert_eq!(decode(image), Ok("BGKAEREZ".to_string()));
+    }
+
+    #[test]
+    fn decode_unknown_glyphs() {
+        let image = "\
+#..#.#...
+#..#.#...
+####.##..
+#..#.#...
+#..#.#...
+#..#.#...
+";
+        assert_eq!(decode(image), Err(vec![UnknownGlyph{
+            index: 1,
+            column: 5,
+            rows: vec!["#...", "#...", "##..", "#...", "#...", "#..."].into_iter().map(String::from).collect(),
+        }]));
+    }
+
+    #[test]
+    fn assemble_source() {
+        let source = "\
+; move X right and back
+.macro wiggle n
+    addx n
+    addx -n   ; and back
+.endm
+.macro twice n
+    wiggle n
+    wiggle n
+.endm
+start: noop
+twice 3
+end:
+    addx end
+    addx -start
+";
+        assert_eq!(assemble(source), Ok(vec!["noop", "addx 3", "addx -3", "addx 3", "addx -3", "addx 10", "addx -1"]
+            .into_iter().map(String::from).collect()));
+    }
+
+    #[test]
+    fn assemble_errors() {
+        assert_eq!(assemble("noop\nmulx 2"), Err((2, AssembleError::UnknownInstruction("mulx".to_string()))));
+        assert_eq!(assemble("addx there"), Err((1, AssembleError::UnknownSymbol("there".to_string()))));
+        assert_eq!(assemble("a:\na: noop"), Err((2, AssembleError::DuplicateLabel("a".to_string()))));
+        assert_eq!(assemble(".macro m\nnoop\n"), Err((1, AssembleError::UnterminatedMacro("m".to_string()))));
+        assert_eq!(assemble(".macro m\nm\n.endm\nm"), Err((2, AssembleError::MacroTooDeep("m".to_string()))));
+        assert_eq!(assemble("addx 1 2"), Err((1, AssembleError::WrongOperandCount{expected: 1, found: 2})));
+    }
+
+    #[test]
+    fn disassemble_program() {
+        let program = parse_program("noop\naddx 3\naddx 0").unwrap();
+        assert_eq!(disassemble(&program), "\
+noop        ; cycle 1, X=1
+addx 3      ; cycles 2-3, X=1 -> 4
+addx 0      ; cycles 4-5, X=4
+");
+    }
+
+    #[test]
+    fn synthesise_input_image() {
+        let program = parse_program(include_str!("../input.txt")).unwrap();
+        let mut crt = Crt::default();
+        Cpu::new().run(&program, &mut [&mut crt]);
+        let synthesised = synthesise(&crt.output).unwrap();
+        let mut redrawn = Crt::default();
+        Cpu::new().run(&parse_program(&synthesised.join("\n")).unwrap(), &mut [&mut redrawn]);
+        assert_eq!(redrawn.output, crt.output);
+        assert_eq!(decode(&redrawn.output), Ok("BGKAEREZ".to_string()));
+    }
+
+    #[test]
+    fn synthesise_errors() {
+        assert_eq!(synthesise("##"), Err(SynthesisError::BadImage));
+        // the sprite starts over the first pixels, and can't move until the end of cycle 2
+        let mut image = format!("{}\n", ".".repeat(CRT_WIDTH)).repeat(CRT_HEIGHT);
+        assert_eq!(synthesise(&image), Err(SynthesisError::Impossible(0, 0)));
+        image.replace_range(0..2, "##");
+        assert_eq!(synthesise(&image), Ok(vec!["addx -3".to_string()].into_iter()
+            .chain(vec!["noop".to_string(); 238]).collect()));
+        image.replace_range(0..4, "#.#.");
+        assert_eq!(synthesise(&image), Err(SynthesisError::Impossible(0, 1)));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_program("noop\nmulx 3").err(), Some((2, ParseError::UnknownInstruction("mulx".to_string()))));
+        assert_eq!(parse_program("addx").err(), Some((1, ParseError::WrongOperandCount{expected: 1, found: 0})));
+        assert_eq!(parse_program("add q 1").err(), Some((1, ParseError::BadOperand("q".to_string()))));
+        assert_eq!(parse_program("addx 1.5").err(), Some((1, ParseError::BadOperand("1.5".to_string()))));
+    }
 }
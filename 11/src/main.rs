@@ -1,104 +1,795 @@
 use std::io;
 use std::io::Read;
+use std::env;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Display};
+use std::ops::RangeInclusive;
+use num::BigUint;
+use num::integer::lcm;
 
-struct Monkey<'a> {
+/// the right hand side of `new = ...`
+#[derive(Clone, Debug, PartialEq)]
+enum Operation {
+    Old,
+    Constant(usize),
+    Add(Box<Operation>, Box<Operation>),
+    Multiply(Box<Operation>, Box<Operation>),
+}
+
+impl Operation {
+    fn apply<A: Arithmetic>(&self, old: &A::Value, arithmetic: &A) -> A::Value {
+        match self {
+            Operation::Old => old.clone(),
+            Operation::Constant(n) => arithmetic.value(*n),
+            Operation::Add(a, b) => arithmetic.add(&a.apply(old, arithmetic), &b.apply(old, arithmetic)),
+            Operation::Multiply(a, b) => arithmetic.multiply(&a.apply(old, arithmetic), &b.apply(old, arithmetic)),
+        }
+    }
+
+    /// the result modulo the modulus, for an old value already below it
+    fn apply_modulo(&self, old: usize, modulus: usize) -> usize {
+        // only widen when the sum or product would overflow, as u128 division is slow
+        let widened = |n: Option<usize>, a: usize, b: usize, f: fn(u128, u128) -> u128| match n {
+            Some(n) => n % modulus,
+            None => (f(a as u128, b as u128) % modulus as u128) as usize,
+        };
+        match self {
+            Operation::Old => old,
+            Operation::Constant(n) => n % modulus,
+            Operation::Add(a, b) => {
+                let (a, b) = (a.apply_modulo(old, modulus), b.apply_modulo(old, modulus));
+                widened(a.checked_add(b), a, b, |a, b| a + b)
+            },
+            Operation::Multiply(a, b) => {
+                let (a, b) = (a.apply_modulo(old, modulus), b.apply_modulo(old, modulus));
+                widened(a.checked_mul(b), a, b, |a, b| a * b)
+            },
+        }
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Old => write!(f, "old"),
+            Operation::Constant(n) => write!(f, "{n}"),
+            Operation::Add(a, b) => write!(f, "{a} + {b}"),
+            Operation::Multiply(a, b) => {
+                // only sums bind more loosely than a product
+                let bracket = |op: &Operation| match op {
+                    Operation::Add(_, _) => format!("({op})"),
+                    _ => op.to_string(),
+                };
+                write!(f, "{} * {}", bracket(a), bracket(b))
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Old,
+    Number(usize),
+    Plus,
+    Times,
+    Open,
+    Close,
+}
+
+/// Only + and * are allowed. Subtraction would reduce fine modulo the product
+/// of the divisors, but worry levels are unsigned, so it could underflow.
+/// Division can't be reduced modulo the divisors at all.
+fn tokenise(input: &str) -> Result<Vec<Token>, String> {
+    let mut ret = vec![];
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' => { chars.next(); },
+            '+' => { chars.next(); ret.push(Token::Plus); },
+            '*' => { chars.next(); ret.push(Token::Times); },
+            '(' => { chars.next(); ret.push(Token::Open); },
+            ')' => { chars.next(); ret.push(Token::Close); },
+            '0'..='9' => {
+                let mut n = String::new();
+                while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                    n.push(d);
+                }
+                ret.push(Token::Number(n.parse().map_err(|_| format!("{n} is too large"))?));
+            },
+            'a'..='z' => {
+                let mut word = String::new();
+                while let Some(l) = chars.next_if(char::is_ascii_lowercase) {
+                    word.push(l);
+                }
+                if word != "old" {
+                    return Err(format!("unknown variable {word}"));
+                }
+                ret.push(Token::Old);
+            },
+            '-' => return Err("- isn't supported, as worry levels are unsigned".to_string()),
+            '/' => return Err("/ isn't supported, as it can't be reduced modulo the divisors".to_string()),
+            _ => return Err(format!("unexpected character {c}")),
+        }
+    }
+    Ok(ret)
+}
+
+/// expression = term ('+' term)*
+/// term = factor ('*' factor)*
+/// factor = 'old' | number | '(' expression ')'
+struct ExpressionParser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl ExpressionParser {
+    fn parse(input: &str) -> Result<Operation, String> {
+        let mut parser = Self{tokens: tokenise(input)?, position: 0};
+        let ret = parser.expression()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(ret),
+            Some(token) => Err(format!("unexpected {token:?}")),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Operation, String> {
+        let mut ret = self.term()?;
+        while self.tokens.get(self.position) == Some(&Token::Plus) {
+            self.position += 1;
+            ret = Operation::Add(Box::new(ret), Box::new(self.term()?));
+        }
+        Ok(ret)
+    }
+
+    fn term(&mut self) -> Result<Operation, String> {
+        let mut ret = self.factor()?;
+        while self.tokens.get(self.position) == Some(&Token::Times) {
+            self.position += 1;
+            ret = Operation::Multiply(Box::new(ret), Box::new(self.factor()?));
+        }
+        Ok(ret)
+    }
+
+    fn factor(&mut self) -> Result<Operation, String> {
+        let token = self.tokens.get(self.position).ok_or("unexpected end of expression")?;
+        self.position += 1;
+        match token {
+            Token::Old => Ok(Operation::Old),
+            Token::Number(n) => Ok(Operation::Constant(*n)),
+            Token::Open => {
+                let ret = self.expression()?;
+                if self.tokens.get(self.position) != Some(&Token::Close) {
+                    return Err("missing )".to_string());
+                }
+                self.position += 1;
+                Ok(ret)
+            },
+            _ => Err(format!("unexpected {token:?}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Monkey {
+    number: usize,
     items: Vec<usize>,
-    operation: Box<dyn Fn(usize) -> usize + 'a>,
+    operation: Operation,
     divisor: usize,
     true_monkey: usize,
     false_monkey: usize,
-    inspections: usize,
+    inspections: u64,
 }
 
-fn main() {
-    let mut buf = String::new();
-    io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
+impl Display for Monkey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let items: Vec<String> = self.items.iter().map(|i| i.to_string()).collect();
+        writeln!(f, "Monkey {}:", self.number)?;
+        writeln!(f, "  Starting items: {}", items.join(", "))?;
+        writeln!(f, "  Operation: new = {}", self.operation)?;
+        writeln!(f, "  Test: divisible by {}", self.divisor)?;
+        writeln!(f, "    If true: throw to monkey {}", self.true_monkey)?;
+        writeln!(f, "    If false: throw to monkey {}", self.false_monkey)
+    }
+}
+
+/// problems in the notes, with the 1-based line number they were found on
+#[derive(Debug, PartialEq)]
+enum NotesError {
+    MissingLine{line: usize, expected: &'static str},
+    BadLine{line: usize, expected: &'static str},
+    BadNumber{line: usize, value: String},
+    /// includes the unsupported operators, - and /
+    BadExpression{line: usize, reason: String},
+    OutOfOrder{line: usize, number: usize},
+    ZeroDivisor{line: usize},
+    UnknownTarget{line: usize, target: usize},
+}
+
+const HEADINGS: [&str; 6] = [
+    "Monkey ",
+    "Starting items:",
+    "Operation: new =",
+    "Test: divisible by",
+    "If true: throw to monkey",
+    "If false: throw to monkey",
+];
+
+fn parse_notes(input: &str) -> Result<Vec<Monkey>, NotesError> {
     let mut monkeys = vec![];
-    let mut divisors = vec![];
-    for line in buf.lines() {
-        process_line(line, &mut monkeys, &mut divisors);
+    let mut targets = vec![];
+    let mut lines = input.lines().enumerate().map(|(n, l)| (n + 1, l.trim())).filter(|(_, l)| !l.is_empty()).peekable();
+    while lines.peek().is_some() {
+        let mut fields = vec![];
+        let mut last = 0;
+        for heading in HEADINGS {
+            let (line, text) = lines.next().ok_or(NotesError::MissingLine{line: last + 1, expected: heading})?;
+            let field = text.strip_prefix(heading).ok_or(NotesError::BadLine{line, expected: heading})?;
+            fields.push((line, field.trim()));
+            last = line;
+        }
+        let number = |(line, value): (usize, &str)| value.parse::<usize>()
+            .map_err(|_| NotesError::BadNumber{line, value: value.to_string()});
+        let (line, header) = fields[0];
+        let id = number((line, header.strip_suffix(':').ok_or(NotesError::BadLine{line, expected: "Monkey N:"})?))?;
+        if id != monkeys.len() {
+            return Err(NotesError::OutOfOrder{line, number: id});
+        }
+        let items = if fields[1].1.is_empty() {
+            vec![]
+        } else {
+            fields[1].1.split(',').map(|i| number((fields[1].0, i.trim()))).collect::<Result<_, _>>()?
+        };
+        let operation = ExpressionParser::parse(fields[2].1)
+            .map_err(|reason| NotesError::BadExpression{line: fields[2].0, reason})?;
+        let divisor = number(fields[3])?;
+        if divisor == 0 {
+            return Err(NotesError::ZeroDivisor{line: fields[3].0});
+        }
+        let true_monkey = number(fields[4])?;
+        let false_monkey = number(fields[5])?;
+        targets.push((fields[4].0, true_monkey));
+        targets.push((fields[5].0, false_monkey));
+        monkeys.push(Monkey{number: id, items, operation, divisor, true_monkey, false_monkey, inspections: 0});
+    }
+    if let Some(&(line, target)) = targets.iter().find(|(_, t)| *t >= monkeys.len()) {
+        return Err(NotesError::UnknownTarget{line, target});
+    }
+    Ok(monkeys)
+}
+
+/// how worry levels are stored and combined
+trait Arithmetic {
+    type Value: Clone + Debug;
+    fn new(divisors: &[usize]) -> Self;
+    fn value(&self, n: usize) -> Self::Value;
+    fn add(&self, a: &Self::Value, b: &Self::Value) -> Self::Value;
+    fn multiply(&self, a: &Self::Value, b: &Self::Value) -> Self::Value;
+    /// is the value divisible by the divisor with this index?
+    fn divisible(&self, a: &Self::Value, index: usize) -> bool;
+    /// divide by 3 and round down, if that's possible for this representation
+    fn relieve(&self, a: &Self::Value) -> Option<Self::Value>;
+    /// replaces the worry with the result of the operation
+    fn evaluate(&self, operation: &Operation, worry: &mut Self::Value) where Self: Sized {
+        *worry = operation.apply(worry, self);
+    }
+}
+
+/// exact worry levels, however large they get
+struct Exact {
+    divisors: Vec<BigUint>,
+}
+
+impl Arithmetic for Exact {
+    type Value = BigUint;
+    fn new(divisors: &[usize]) -> Self {
+        Self{divisors: divisors.iter().map(|&d| BigUint::from(d)).collect()}
+    }
+    fn value(&self, n: usize) -> BigUint {
+        BigUint::from(n)
+    }
+    fn add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a + b
     }
-    for _ in 0..20 {
-        for i in 0..monkeys.len() {
-            monkey_turn(i, &mut monkeys, &divisors, true);
+    fn multiply(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a * b
+    }
+    fn divisible(&self, a: &BigUint, index: usize) -> bool {
+        (a % &self.divisors[index]) == BigUint::ZERO
+    }
+    fn relieve(&self, a: &BigUint) -> Option<BigUint> {
+        Some(a / 3_u32)
+    }
+}
+
+/// worry levels modulo the lowest common multiple of the divisors
+struct ModuloLcm {
+    modulus: usize,
+    divisors: Vec<usize>,
+}
+
+impl Arithmetic for ModuloLcm {
+    type Value = usize;
+    fn new(divisors: &[usize]) -> Self {
+        Self{modulus: divisors.iter().fold(1, |m, &d| lcm(m, d)), divisors: divisors.to_vec()}
+    }
+    fn value(&self, n: usize) -> usize {
+        n % self.modulus
+    }
+    fn add(&self, a: &usize, b: &usize) -> usize {
+        (a + b) % self.modulus
+    }
+    fn multiply(&self, a: &usize, b: &usize) -> usize {
+        ((*a as u128 * *b as u128) % self.modulus as u128) as usize
+    }
+    fn divisible(&self, a: &usize, index: usize) -> bool {
+        a.is_multiple_of(self.divisors[index])
+    }
+    fn relieve(&self, _: &usize) -> Option<usize> {
+        None
+    }
+    fn evaluate(&self, operation: &Operation, worry: &mut usize) {
+        *worry = operation.apply_modulo(*worry, self.modulus);
+    }
+}
+
+/// one residue per divisor
+struct Residues {
+    divisors: Vec<usize>,
+}
+
+impl Arithmetic for Residues {
+    type Value = Vec<usize>;
+    fn new(divisors: &[usize]) -> Self {
+        Self{divisors: divisors.to_vec()}
+    }
+    fn value(&self, n: usize) -> Vec<usize> {
+        self.divisors.iter().map(|d| n % d).collect()
+    }
+    fn add(&self, a: &Vec<usize>, b: &Vec<usize>) -> Vec<usize> {
+        a.iter().zip(b).zip(&self.divisors).map(|((&x, &y), &d)| ((x as u128 + y as u128) % d as u128) as usize).collect()
+    }
+    fn multiply(&self, a: &Vec<usize>, b: &Vec<usize>) -> Vec<usize> {
+        a.iter().zip(b).zip(&self.divisors).map(|((&x, &y), &d)| ((x as u128 * y as u128) % d as u128) as usize).collect()
+    }
+    fn divisible(&self, a: &Vec<usize>, index: usize) -> bool {
+        a[index] == 0
+    }
+    fn relieve(&self, _: &Vec<usize>) -> Option<Vec<usize>> {
+        None
+    }
+    /// works residue by residue, in place, so nothing is allocated
+    fn evaluate(&self, operation: &Operation, worry: &mut Vec<usize>) {
+        for (r, &d) in worry.iter_mut().zip(&self.divisors) {
+            *r = operation.apply_modulo(*r, d);
         }
     }
-    println!("monkey business: {}", monkey_business(&monkeys));
-    let mut monkeys = vec![];
-    let mut divisors = vec![];
-    for line in buf.lines() {
-        process_line(line, &mut monkeys, &mut divisors);
-    }
-    for _ in 0..10000 {
-        for i in 0..monkeys.len() {
-            monkey_turn(i, &mut monkeys, &divisors, false);
-        }
-    }
-    println!("monkey business: {}", monkey_business(&monkeys));
-}
-
-fn process_line<'a>(line: &'a str, monkeys: &mut Vec<Monkey<'a>>, divisors: &mut Vec<usize>) {
-    if line.contains("Monkey") {
-        monkeys.push(Monkey{
-            items: vec![],
-            operation: Box::new(|_| 1),
-            divisor: 0,
-            true_monkey: 0,
-            false_monkey: 0,
-            inspections: 0,
-        });
-    }
-    if line.contains("Starting items:") {
-        let split = line.split([':', ',']).skip(1);
-        monkeys.last_mut().unwrap().items = split.map(|x| x.trim().parse::<usize>().unwrap()).collect();
-    }
-    if line.contains("Operation:") {
-        let mut split = line.split(" ").skip(6);
-        let op = split.next().unwrap();
-        let b = split.next().unwrap();
-        monkeys.last_mut().unwrap().operation = match (op, b) {
-            ("+", "old") => {Box::new(|x| x+x)},
-            ("*", "old") => {Box::new(|x| x*x)},
-            ("+", _) => {Box::new(|x| x + b.parse::<usize>().unwrap())},
-            ("*", _) => {Box::new(|x| x * b.parse::<usize>().unwrap())},
-            _ => unimplemented!(),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Backend {
+    Exact,
+    ModuloLcm,
+    Residues,
+}
+
+impl TryFrom<&str> for Backend {
+    type Error = ();
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "exact" => Ok(Backend::Exact),
+            "lcm" => Ok(Backend::ModuloLcm),
+            "residues" => Ok(Backend::Residues),
+            _ => Err(()),
+        }
+    }
+}
+
+/// one item being thrown, with its worry before inspection and as thrown
+#[derive(Clone, Debug, PartialEq)]
+struct Throw<V> {
+    round: u64,
+    from: usize,
+    to: usize,
+    before: V,
+    after: V,
+}
+
+impl<V: Debug> Display for Throw<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "round {}: monkey {} -> {}, worry {:?} -> {:?}", self.round, self.from, self.to, self.before, self.after)
+    }
+}
+
+/// which throws to keep; None matches anything
+#[derive(Clone, Debug, Default)]
+struct ThrowFilter {
+    rounds: Option<RangeInclusive<u64>>,
+    from: Option<usize>,
+    to: Option<usize>,
+}
+
+impl ThrowFilter {
+    fn matches<V>(&self, throw: &Throw<V>) -> bool {
+        self.rounds.as_ref().is_none_or(|r| r.contains(&throw.round))
+            && self.from.is_none_or(|m| m == throw.from)
+            && self.to.is_none_or(|m| m == throw.to)
+    }
+}
+
+/// a DOT graph of how often each monkey threw to each other monkey,
+/// with each monkey labelled by its inspection count
+fn throw_graph<V>(monkeys: &[Monkey], log: &[Throw<V>]) -> String {
+    let mut counts: BTreeMap<(usize, usize), u64> = BTreeMap::new();
+    for throw in log {
+        *counts.entry((throw.from, throw.to)).or_default() += 1;
+    }
+    let most = counts.values().max().copied().unwrap_or(1);
+    let mut ret = "digraph throws {\n".to_string();
+    for monkey in monkeys {
+        ret.push_str(&format!("    {}[label=\"{}({})\"];\n", monkey.number, monkey.number, monkey.inspections));
+    }
+    for ((from, to), count) in counts {
+        let width = 1 + 4 * count / most;
+        ret.push_str(&format!("    {from}->{to}[label=\"{count}\", penwidth={width}];\n"));
+    }
+    ret.push_str("}\n");
+    ret
+}
+
+/// a run through the monkeys' notes, with the items' worry levels held in
+/// the chosen arithmetic
+struct Simulation<A: Arithmetic> {
+    monkeys: Vec<Monkey>,
+    arithmetic: A,
+    items: Vec<Vec<A::Value>>,
+    relief: bool,
+    round: u64,
+    log: Option<Vec<Throw<A::Value>>>,
+}
+
+impl<A: Arithmetic> Simulation<A> {
+    /// None if relief is wanted but the arithmetic can't divide
+    fn new(notes: &[Monkey], relief: bool) -> Option<Self> {
+        let divisors: Vec<usize> = notes.iter().map(|m| m.divisor).collect();
+        let arithmetic = A::new(&divisors);
+        if relief && arithmetic.relieve(&arithmetic.value(0)).is_none() {
+            return None;
+        }
+        let items = notes.iter().map(|m| m.items.iter().map(|&i| arithmetic.value(i)).collect()).collect();
+        Some(Self{monkeys: notes.to_vec(), arithmetic, items, relief, round: 0, log: None})
+    }
+
+    fn with_log(mut self) -> Self {
+        self.log = Some(vec![]);
+        self
+    }
+
+    fn rounds(&mut self, count: u64) {
+        for _ in 0..count {
+            self.round += 1;
+            for i in 0..self.monkeys.len() {
+                self.monkey_turn(i);
+            }
+        }
+    }
+
+    fn monkey_turn(&mut self, i: usize) {
+        for mut worry in std::mem::take(&mut self.items[i]) {
+            // only keep the worry from before the operation when it's logged
+            let before = self.log.is_some().then(|| worry.clone());
+            self.arithmetic.evaluate(&self.monkeys[i].operation, &mut worry);
+            if self.relief {
+                worry = self.arithmetic.relieve(&worry).unwrap();
+            }
+            let m = if self.arithmetic.divisible(&worry, i) {
+                self.monkeys[i].true_monkey
+            } else {
+                self.monkeys[i].false_monkey
+            };
+            if let (Some(log), Some(before)) = (self.log.as_mut(), before) {
+                log.push(Throw{round: self.round, from: i, to: m, before, after: worry.clone()});
+            }
+            self.items[m].push(worry);
+            self.monkeys[i].inspections += 1;
+        }
+    }
+}
+
+/// None if the arithmetic can't handle relief
+fn run(notes: &[Monkey], backend: Backend, rounds: u64, relief: bool) -> Option<Vec<Monkey>> {
+    fn with<A: Arithmetic>(notes: &[Monkey], rounds: u64, relief: bool) -> Option<Vec<Monkey>> {
+        let mut simulation = Simulation::<A>::new(notes, relief)?;
+        simulation.rounds(rounds);
+        Some(simulation.monkeys)
+    }
+    match backend {
+        Backend::Exact => with::<Exact>(notes, rounds, relief),
+        Backend::ModuloLcm => with::<ModuloLcm>(notes, rounds, relief),
+        Backend::Residues => with::<Residues>(notes, rounds, relief),
+    }
+}
+
+/// print the filtered throw log and/or the throw graph for a logged run
+fn report(notes: &[Monkey], backend: Backend, rounds: u64, relief: bool, filter: &ThrowFilter, dot: bool) -> bool {
+    fn with<A: Arithmetic>(notes: &[Monkey], rounds: u64, relief: bool, filter: &ThrowFilter, dot: bool) -> bool {
+        let Some(simulation) = Simulation::<A>::new(notes, relief) else {
+            return false;
         };
+        let mut simulation = simulation.with_log();
+        simulation.rounds(rounds);
+        let log: Vec<Throw<A::Value>> = simulation.log.unwrap().into_iter().filter(|t| filter.matches(t)).collect();
+        if dot {
+            print!("{}", throw_graph(&simulation.monkeys, &log));
+        } else {
+            log.iter().for_each(|throw| println!("{throw}"));
+        }
+        true
     }
-    if line.contains("Test:") {
-        let divisor = line.split("divisible by ").last().unwrap().parse::<usize>().unwrap();
-        monkeys.last_mut().unwrap().divisor = divisor;
-        divisors.push(divisor);
+    match backend {
+        Backend::Exact => with::<Exact>(notes, rounds, relief, filter, dot),
+        Backend::ModuloLcm => with::<ModuloLcm>(notes, rounds, relief, filter, dot),
+        Backend::Residues => with::<Residues>(notes, rounds, relief, filter, dot),
     }
-    if line.contains("If true:") {
-        monkeys.last_mut().unwrap().true_monkey = line.split("throw to monkey ").last().unwrap().parse::<usize>().unwrap();
+}
+
+/// Without relief each item moves independently of the others, and where it
+/// starts a round (its monkey, and its worry modulo the LCM) fixes the rest of
+/// its path. So each item's path is eventually periodic, and once a start
+/// state repeats the remaining rounds can be counted without simulating them.
+fn fast_forward(notes: &[Monkey], rounds: u64) -> Vec<Monkey> {
+    let divisors: Vec<usize> = notes.iter().map(|m| m.divisor).collect();
+    let arithmetic = ModuloLcm::new(&divisors);
+    let mut monkeys = notes.to_vec();
+    for (start, monkey) in notes.iter().enumerate() {
+        for &item in &monkey.items {
+            let totals = item_inspections(notes, &arithmetic, (start, arithmetic.value(item)), rounds);
+            for (monkey, total) in monkeys.iter_mut().zip(totals) {
+                monkey.inspections += total;
+            }
+        }
     }
-    if line.contains("If false:") {
-        monkeys.last_mut().unwrap().false_monkey = line.split("throw to monkey ").last().unwrap().parse::<usize>().unwrap();
+    monkeys
+}
+
+/// how many times each monkey inspects one item over the given rounds
+fn item_inspections(notes: &[Monkey], arithmetic: &ModuloLcm, start: (usize, usize), rounds: u64) -> Vec<u64> {
+    let mut seen = HashMap::new();
+    // inspection counts after each round so far
+    let mut history = vec![vec![0; notes.len()]];
+    let mut state = start;
+    for round in 0..rounds {
+        if let Some(&first) = seen.get(&state) {
+            let period = round - first;
+            let (cycles, rest) = ((rounds - round) / period, (rounds - round) % period);
+            let [first, round, partial] = [first, round, first + rest].map(|r| &history[r as usize]);
+            return (0..notes.len())
+                .map(|m| round[m] + cycles * (round[m] - first[m]) + (partial[m] - first[m]))
+                .collect();
+        }
+        seen.insert(state, round);
+        let mut counts = history.last().unwrap().clone();
+        let (mut monkey, mut worry) = state;
+        // a throw to a later monkey is inspected again this round
+        loop {
+            counts[monkey] += 1;
+            worry = notes[monkey].operation.apply(&worry, arithmetic);
+            let next = if arithmetic.divisible(&worry, monkey) {
+                notes[monkey].true_monkey
+            } else {
+                notes[monkey].false_monkey
+            };
+            let later = next > monkey;
+            monkey = next;
+            if !later {
+                break;
+            }
+        }
+        state = (monkey, worry);
+        history.push(counts);
     }
+    history.pop().unwrap()
 }
 
-fn monkey_turn(i: usize, monkeys: &mut Vec<Monkey>, divisors: &Vec<usize>, capped_worry: bool) {
-    for item in monkeys[i].items.clone() {
-        let mut worry = (monkeys[i].operation)(item);
-        if capped_worry {
-            worry = (worry as f32 / 3_f32).floor() as usize;
+fn main() {
+    let mut buf = String::new();
+    io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
+    let notes = match parse_notes(&buf) {
+        Ok(monkeys) => monkeys,
+        Err(e) => {
+            println!("Error in notes: {e:?}");
+            return;
+        },
+    };
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut backend = Backend::ModuloLcm;
+    let mut rounds = None;
+    let mut skip_cycles = false;
+    let mut log = None;
+    let mut filter = ThrowFilter::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--arithmetic" => {
+                // part one always uses exact arithmetic, as relief needs division
+                backend = Backend::try_from(args.next().map_or("", String::as_str))
+                    .expect("Arithmetic for part two should be one of exact, lcm or residues");
+            },
+            "--rounds" => rounds = Some(args.next().and_then(|r| r.parse().ok()).expect("Rounds should be a number")),
+            "--fast-forward" => skip_cycles = true,
+            "--log" => log = Some(false),
+            "--dot" => log = Some(true),
+            "--from" => filter.from = Some(args.next().and_then(|m| m.parse().ok()).expect("From should be a monkey number")),
+            "--to" => filter.to = Some(args.next().and_then(|m| m.parse().ok()).expect("To should be a monkey number")),
+            "--round" => filter.rounds = Some(args.next().and_then(|r| {
+                let (from, to) = r.split_once('-').unwrap_or((r, r));
+                Some(from.parse().ok()?..=to.parse().ok()?)
+            }).expect("Round should be a number or a range like 3-7")),
+            _ => panic!("Unknown option {arg}"),
         }
-        worry = worry % divisors.iter().product::<usize>();
-        if worry % monkeys[i].divisor == 0 {
-            let m = monkeys[i].true_monkey;
-            monkeys[m].items.push(worry);
+    }
+    if backend == Backend::Exact && rounds.is_none() && !skip_cycles {
+        // without relief, exact worry levels grow without bound
+        panic!("Exact arithmetic for part two needs --rounds, as worry levels grow without bound");
+    }
+    let rounds = rounds.unwrap_or(10000);
+    if let Some(dot) = log {
+        if !report(&notes, backend, rounds, false, &filter, dot) {
+            println!("Can't log with {backend:?} arithmetic");
+        }
+        return;
+    }
+    for (backend, rounds, relief) in [(Backend::Exact, 20, true), (backend, rounds, false)] {
+        let monkeys = if skip_cycles && !relief {
+            Some(fast_forward(&notes, rounds))
         } else {
-            let m = monkeys[i].false_monkey;
-            monkeys[m].items.push(worry);
+            run(&notes, backend, rounds, relief)
+        };
+        match monkeys {
+            Some(monkeys) => println!("monkey business: {}", monkey_business(&monkeys)),
+            None => println!("monkey business can't be found with {backend:?} arithmetic when worry is relieved"),
         }
-        monkeys[i].inspections += 1;
     }
-    monkeys[i].items = vec![];
 }
 
-fn monkey_business(monkeys: &Vec<Monkey>) -> usize {
-    let mut insps: Vec<usize> = monkeys.iter().map(|m| m.inspections).collect();
+fn monkey_business(monkeys: &[Monkey]) -> u128 {
+    let mut insps: Vec<u64> = monkeys.iter().map(|m| m.inspections).collect();
     insps.sort_unstable();
     insps.reverse();
-    insps[0] * insps[1]
-}
\ No newline at end of file
+    insps[0] as u128 * insps[1] as u128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../example.txt");
+
+    #[test]
+    fn operations() {
+        let op = ExpressionParser::parse("old * (old + 3) + 2 * old").unwrap();
+        assert_eq!(op.apply(&BigUint::from(5_u8), &Exact::new(&[])), BigUint::from(5_u8 * 8 + 10));
+        assert_eq!(op.to_string(), "old * (old + 3) + 2 * old");
+        assert_eq!(ExpressionParser::parse("((old))").unwrap(), Operation::Old);
+        assert_eq!(ExpressionParser::parse("(old + 1) * (2 + old)").unwrap().to_string(), "(old + 1) * (2 + old)");
+        assert!(ExpressionParser::parse("old / 2").is_err());
+        assert!(ExpressionParser::parse("old + ").is_err());
+        assert!(ExpressionParser::parse("(old").is_err());
+        assert!(ExpressionParser::parse("new * 2").is_err());
+        assert!(ExpressionParser::parse("old 2").is_err());
+    }
+
+    #[test]
+    fn notes_round_trip() {
+        let monkeys = parse_notes(EXAMPLE).unwrap();
+        assert_eq!(monkeys.len(), 4);
+        let printed: Vec<String> = monkeys.iter().map(|m| m.to_string()).collect();
+        assert_eq!(printed.join("\n"), EXAMPLE.trim_end().to_string() + "\n");
+        assert_eq!(parse_notes(&printed.join("\n")).unwrap(), monkeys);
+    }
+
+    #[test]
+    fn notes_errors() {
+        let example = |from: &str, to: &str| parse_notes(&EXAMPLE.replacen(from, to, 1));
+        assert_eq!(example("new = old * 19", "new = old / 19"),
+            Err(NotesError::BadExpression{line: 3, reason: "/ isn't supported, as it can't be reduced modulo the divisors".to_string()}));
+        assert_eq!(example("new = old * 19", "new = old - 19"),
+            Err(NotesError::BadExpression{line: 3, reason: "- isn't supported, as worry levels are unsigned".to_string()}));
+        assert_eq!(example("new = old * 19", "new = old % 19"),
+            Err(NotesError::BadExpression{line: 3, reason: "unexpected character %".to_string()}));
+        assert_eq!(example("Test: divisible by 23", "Test: divisible by 0"), Err(NotesError::ZeroDivisor{line: 4}));
+        assert_eq!(example("79, 98", "79, x"), Err(NotesError::BadNumber{line: 2, value: "x".to_string()}));
+        assert_eq!(example("Monkey 1:", "Monkey 2:"), Err(NotesError::OutOfOrder{line: 8, number: 2}));
+        assert_eq!(example("throw to monkey 3", "throw to monkey 4"), Err(NotesError::UnknownTarget{line: 6, target: 4}));
+        assert_eq!(example("  Operation", "  Op"), Err(NotesError::BadLine{line: 3, expected: "Operation: new ="}));
+        assert_eq!(parse_notes("Monkey 0:\n  Starting items: 1\n"),
+            Err(NotesError::MissingLine{line: 3, expected: "Operation: new ="}));
+    }
+
+    #[test]
+    fn example_monkey_business() {
+        let monkeys = parse_notes(EXAMPLE).unwrap();
+        assert_eq!(monkey_business(&run(&monkeys, Backend::Exact, 20, true).unwrap()), 10605);
+        assert_eq!(run(&monkeys, Backend::ModuloLcm, 20, true), None);
+        assert_eq!(run(&monkeys, Backend::Residues, 20, true), None);
+        for backend in [Backend::ModuloLcm, Backend::Residues] {
+            assert_eq!(monkey_business(&run(&monkeys, backend, 10000, false).unwrap()), 2713310158);
+        }
+    }
+
+    #[test]
+    fn backends_agree() {
+        let monkeys = parse_notes(EXAMPLE).unwrap();
+        let exact = run(&monkeys, Backend::Exact, 60, false).unwrap();
+        for backend in [Backend::ModuloLcm, Backend::Residues] {
+            let inspections = |ms: &[Monkey]| ms.iter().map(|m| m.inspections).collect::<Vec<u64>>();
+            assert_eq!(inspections(&run(&monkeys, backend, 60, false).unwrap()), inspections(&exact));
+        }
+    }
+
+    #[test]
+    fn residues_dont_overflow() {
+        let large = (1 << 40) + 15;
+        let residues = Residues::new(&[large, 7]);
+        let operation = Operation::Multiply(Box::new(Operation::Old), Box::new(Operation::Old));
+        let mut worry = residues.value(large - 1);
+        residues.evaluate(&operation, &mut worry);
+        assert_eq!(worry, vec![1, ((large - 1) % 7).pow(2) % 7]);
+        assert_eq!(residues.multiply(&worry, &residues.value(large - 2)), vec![large - 2, ((large - 1) % 7).pow(2) * ((large - 2) % 7) % 7]);
+    }
+
+    #[test]
+    fn fast_forward_matches_simulation() {
+        for input in [EXAMPLE, include_str!("../input.txt")] {
+            let monkeys = parse_notes(input).unwrap();
+            for rounds in [0, 1, 7, 20, 1000, 10000] {
+                let simulated = run(&monkeys, Backend::ModuloLcm, rounds, false).unwrap();
+                assert_eq!(fast_forward(&monkeys, rounds), simulated, "{rounds} rounds");
+            }
+        }
+    }
+
+    #[test]
+    fn fast_forward_many_rounds() {
+        let monkeys = parse_notes(EXAMPLE).unwrap();
+        assert_eq!(monkey_business(&fast_forward(&monkeys, 10000)), 2713310158);
+        let total = |rounds| fast_forward(&monkeys, rounds).iter().map(|m| m.inspections).sum::<u64>();
+        assert!(total(1_000_000_000_000) > 1000 * total(1_000_000_000));
+    }
+
+    #[test]
+    fn throw_log() {
+        let monkeys = parse_notes(EXAMPLE).unwrap();
+        let mut simulation = Simulation::<Exact>::new(&monkeys, true).unwrap().with_log();
+        simulation.rounds(2);
+        let log = simulation.log.unwrap();
+        assert_eq!(log.len() as u64, simulation.monkeys.iter().map(|m| m.inspections).sum::<u64>());
+        assert_eq!(log[0], Throw{round: 1, from: 0, to: 3, before: BigUint::from(79_u8), after: BigUint::from(500_u16)});
+        assert_eq!(log[0].to_string(), "round 1: monkey 0 -> 3, worry 79 -> 500");
+        let filter = ThrowFilter{rounds: Some(2..=2), from: Some(1), to: None};
+        let filtered: Vec<&Throw<BigUint>> = log.iter().filter(|t| filter.matches(t)).collect();
+        assert!(!filtered.is_empty());
+        assert!(filtered.iter().all(|t| t.round == 2 && t.from == 1));
+        assert!(ThrowFilter{to: Some(1), ..Default::default()}.matches(&log[0]) == (log[0].to == 1));
+    }
+
+    #[test]
+    fn throw_graph_dot() {
+        let monkeys = parse_notes(EXAMPLE).unwrap();
+        let mut simulation = Simulation::<Exact>::new(&monkeys, true).unwrap().with_log();
+        simulation.rounds(1);
+        let dot = throw_graph(&simulation.monkeys, &simulation.log.unwrap());
+        assert_eq!(dot, "\
+digraph throws {
+    0[label=\"0(2)\"];
+    1[label=\"1(4)\"];
+    2[label=\"2(3)\"];
+    3[label=\"3(5)\"];
+    0->3[label=\"2\", penwidth=2];
+    1->0[label=\"4\", penwidth=4];
+    2->1[label=\"1\", penwidth=1];
+    2->3[label=\"2\", penwidth=2];
+    3->1[label=\"5\", penwidth=5];
+}
+");
+    }
+}
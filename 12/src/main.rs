@@ -1,5 +1,8 @@
 use std::{io};
 use std::io::Read;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::env;
 
 const START: char = 'S';
 const END: char = 'E';
@@ -10,36 +13,124 @@ struct Square {
 
 impl Square {
     fn height(&self) -> u8 {
+        self.height_as('a', 'z')
+    }
+
+    /// the height, treating the start and end as the given elevations
+    fn height_as(&self, start: char, end: char) -> u8 {
         match self.elevation {
-            START => 0,
-            END => 25,
+            START => start as u8 - 97,
+            END => end as u8 - 97,
             _ => self.elevation as u8 - 97,
         }
     }
 }
 
+/// which steps are allowed, and what each costs given the change in height
+struct ClimbingRules {
+    max_ascent: u8,
+    max_descent: u8,
+    step_cost: Box<dyn Fn(i32) -> usize>,
+    start_elevation: char,
+    end_elevation: char,
+}
+
+impl Default for ClimbingRules {
+    fn default() -> Self {
+        Self{
+            max_ascent: 1,
+            max_descent: u8::MAX,
+            step_cost: Box::new(|_| 1),
+            start_elevation: 'a',
+            end_elevation: 'z',
+        }
+    }
+}
+
+impl ClimbingRules {
+    fn height(&self, sv: &SquareVisitor) -> u8 {
+        sv.square.height_as(self.start_elevation, self.end_elevation)
+    }
+
+    /// the cost of a step, if it's allowed
+    fn cost(&self, from: u8, to: u8) -> Option<usize> {
+        let change = to as i32 - from as i32;
+        if change > self.max_ascent as i32 || -change > self.max_descent as i32 {
+            return None;
+        }
+        Some((self.step_cost)(change))
+    }
+
+    /// no step can cost less than this, which keeps the A* heuristic admissible
+    fn cheapest_step(&self) -> usize {
+        let descent = self.max_descent.min(25) as i32;
+        (-descent..=self.max_ascent.min(25) as i32).map(|c| (self.step_cost)(c)).min().unwrap_or(0)
+    }
+}
+
 struct SquareVisitor {
     square: Square,
     visited: bool,
 }
 
+type Point = (usize, usize);
+
 fn main() {
     let mut hill: Vec<Vec<SquareVisitor>> = vec![];
     let mut buf = String::new();
     io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
-    let (start, possible_starts) = initialise_map(&buf, &mut hill);
-    let first_route = shortest_path(start, &mut hill, None);
-    println!("Shortest route: {}", first_route);
-    let mut min = first_route;
-    for st in possible_starts {
-        reset_visitors(&mut hill);
-        min = shortest_path(st, &mut hill, Some(min));
+    let (start, end, possible_starts) = initialise_map(&buf, &mut hill);
+    let distances = distances_to(end, &mut hill);
+    match distances[start.0][start.1] {
+        Some(n) => println!("Shortest route: {}", n),
+        None => println!("There's no route from the start"),
+    }
+    match possible_starts.iter().filter_map(|&(i, j)| distances[i][j]).min() {
+        Some(n) => println!("Shortest of all routes: {}", n),
+        None => println!("There's no route from any of the lowest squares"),
+    }
+    let mut rules = ClimbingRules::default();
+    let mut custom = false;
+    let mut draw = false;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut number = || args.next().and_then(|n| n.parse::<usize>().ok()).expect("Expected a number");
+        match arg.as_str() {
+            "--draw" => draw = true,
+            "--max-ascent" => rules.max_ascent = number() as u8,
+            "--max-descent" => rules.max_descent = number() as u8,
+            "--climb-cost" => {
+                let per_step = number();
+                rules.step_cost = Box::new(move |change| if change > 0 { per_step } else { 1 });
+            },
+            _ => panic!("Unknown option {arg}"),
+        }
+        custom |= arg != "--draw";
+    }
+    if custom {
+        let hike = cheapest_hike(&[start], end, &mut hill, &rules);
+        match &hike {
+            Some((cost, _)) => println!("Cheapest hike: {}", cost),
+            None => println!("There's no hike from the start"),
+        }
+        match cheapest_hike(&possible_starts, end, &mut hill, &rules) {
+            Some((cost, _)) => println!("Cheapest of all hikes: {}", cost),
+            None => println!("There's no hike from any of the lowest squares"),
+        }
+        if let (true, Some((_, route))) = (draw, hike) {
+            print!("{}", draw_route(&route, &hill));
+        }
+    } else if draw {
+        if let Some(route) = route(start, &distances, &hill) {
+            print!("{}", draw_route(&route, &hill));
+        }
     }
-    println!("Shortest of all routes: {}", min);
 }
 
-fn initialise_map(input: &String, map: &mut Vec<Vec<SquareVisitor>>) -> ((usize, usize), Vec<(usize, usize)>) {
+fn initialise_map(input: &str, map: &mut Vec<Vec<SquareVisitor>>) -> (Point, Point, Vec<Point>) {
     let mut start = (0, 0);
+    let mut end = (0, 0);
     let mut starts = vec![];
     for (i, line) in input.lines().enumerate() {
         let mut row = vec![];
@@ -53,6 +144,9 @@ fn initialise_map(input: &String, map: &mut Vec<Vec<SquareVisitor>>) -> ((usize,
             if c == START {
                 start = (i, j);
             }
+            if c == END {
+                end = (i, j);
+            }
             if sv.square.height() == 0 { 
                 starts.push((i, j));
             }
@@ -60,47 +154,132 @@ fn initialise_map(input: &String, map: &mut Vec<Vec<SquareVisitor>>) -> ((usize,
         }
         map.push(row);
     }
-    (start, starts)
+    (start, end, starts)
 }
 
-fn neighbour_coords((i, j): (usize, usize), map: &Vec<Vec<SquareVisitor>>) -> Vec<(usize, usize)> {
+fn neighbour_coords((i, j): Point, map: &[Vec<SquareVisitor>]) -> Vec<Point> {
     [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().filter(|inc| {
         (0..map.len() as isize).contains(&(inc.0 + i as isize))
         && (0..map[0].len() as isize).contains(&(inc.1 + j as isize))
     }).map(|inc| ((inc.0 + i as isize) as usize, (inc.1 + j as isize) as usize)).collect()
 }
 
-fn unvisited_neighbours((i, j): (usize, usize), map: &Vec<Vec<SquareVisitor>>) -> Vec<(usize, usize)> {
+/// unvisited squares from which you could climb to this one
+fn unvisited_predecessors((i, j): Point, map: &[Vec<SquareVisitor>]) -> Vec<Point> {
     let h = map[i][j].square.height();
     neighbour_coords((i, j), map).iter().map(|(ix, jx)| (*ix, *jx)).filter(|(ix, jx)| {
             let sv = &map[*ix][*jx];
-            !sv.visited && sv.square.height() <= h + 1
+            !sv.visited && h <= sv.square.height() + 1
         })
         .collect()
 }
 
-fn shortest_path(start: (usize, usize), hill: &mut Vec<Vec<SquareVisitor>>, min: Option<usize>) -> usize {
-    let mut recent = vec![start];
+/// the length of the shortest route from every square to the goal,
+/// found with a single breadth-first search backwards from the goal
+fn distances_to(goal: Point, hill: &mut [Vec<SquareVisitor>]) -> Vec<Vec<Option<usize>>> {
+    reset_visitors(hill);
+    let mut distances = vec![vec![None; hill[0].len()]; hill.len()];
+    let mut recent = vec![goal];
+    hill[goal.0][goal.1].visited = true;
     let mut n = 0;
-    loop {
-        recent = recent.iter().flat_map(|pt| unvisited_neighbours(*pt, &hill)).collect();
+    while !recent.is_empty() {
+        for pt in &recent {
+            distances[pt.0][pt.1] = Some(n);
+        }
+        recent = recent.iter().flat_map(|pt| unvisited_predecessors(*pt, hill)).collect();
         recent.sort_unstable();
         recent.dedup();
         for pt in &recent {
             hill[pt.0][pt.1].visited = true;
         }
         n += 1;
-        if recent.iter().find(|(i, j)| hill[*i][*j].square.elevation == END).is_some() {
-            break;
+    }
+    distances
+}
+
+/// the squares on a shortest route from start to the goal, including both
+fn route(start: Point, distances: &[Vec<Option<usize>>], hill: &[Vec<SquareVisitor>]) -> Option<Vec<Point>> {
+    let mut ret = vec![start];
+    let mut current = start;
+    let mut remaining = distances[start.0][start.1]?;
+    while remaining > 0 {
+        let h = hill[current.0][current.1].square.height();
+        current = *neighbour_coords(current, hill).iter().find(|(i, j)| {
+            distances[*i][*j] == Some(remaining - 1) && hill[*i][*j].square.height() <= h + 1
+        }).unwrap();
+        ret.push(current);
+        remaining -= 1;
+    }
+    Some(ret)
+}
+
+/// the map with the route marked by arrows, as in the puzzle description
+fn draw_route(route: &[Point], hill: &[Vec<SquareVisitor>]) -> String {
+    let mut drawing = vec![vec!['.'; hill[0].len()]; hill.len()];
+    for pair in route.windows(2) {
+        let ((i, j), (m, n)) = (pair[0], pair[1]);
+        drawing[i][j] = match (m.cmp(&i), n.cmp(&j)) {
+            (Ordering::Less, _) => '^',
+            (Ordering::Greater, _) => 'v',
+            (_, Ordering::Less) => '<',
+            _ => '>',
+        };
+    }
+    if let Some(&(i, j)) = route.last() {
+        drawing[i][j] = END;
+    }
+    drawing.iter().map(|row| row.iter().collect::<String>() + "\n").collect()
+}
+
+/// A* search for the cheapest hike from any of the starts to the goal,
+/// returning its cost and route
+fn cheapest_hike(starts: &[Point], goal: Point, hill: &mut [Vec<SquareVisitor>], rules: &ClimbingRules) -> Option<(usize, Vec<Point>)> {
+    weighted_search(starts, goal, hill, rules, rules.cheapest_step())
+}
+
+/// a cheapest step of 0 turns off the heuristic, leaving Dijkstra
+fn weighted_search(starts: &[Point], goal: Point, hill: &mut [Vec<SquareVisitor>], rules: &ClimbingRules, cheapest_step: usize) -> Option<(usize, Vec<Point>)> {
+    reset_visitors(hill);
+    let estimate = |(i, j): Point| (i.abs_diff(goal.0) + j.abs_diff(goal.1)) * cheapest_step;
+    let mut costs = vec![vec![None; hill[0].len()]; hill.len()];
+    let mut previous = vec![vec![None; hill[0].len()]; hill.len()];
+    let mut queue = BinaryHeap::new();
+    for &start in starts {
+        costs[start.0][start.1] = Some(0);
+        queue.push(Reverse((estimate(start), 0, start)));
+    }
+    while let Some(Reverse((_, cost, current))) = queue.pop() {
+        if hill[current.0][current.1].visited {
+            continue;
         }
-        if min.is_some() && min.unwrap() <= n {
-            return n;
+        hill[current.0][current.1].visited = true;
+        if current == goal {
+            let mut route = vec![goal];
+            while let Some(pt) = previous[route[route.len() - 1].0][route[route.len() - 1].1] {
+                route.push(pt);
+            }
+            route.reverse();
+            return Some((cost, route));
+        }
+        let h = rules.height(&hill[current.0][current.1]);
+        for (i, j) in neighbour_coords(current, hill) {
+            if hill[i][j].visited {
+                continue;
+            }
+            let Some(step) = rules.cost(h, rules.height(&hill[i][j])) else {
+                continue;
+            };
+            if costs[i][j].is_none_or(|c| cost + step < c) {
+                costs[i][j] = Some(cost + step);
+                previous[i][j] = Some(current);
+                queue.push(Reverse((cost + step + estimate((i, j)), cost + step, (i, j))));
+            }
         }
     }
-    n
+    None
 }
 
-fn reset_visitors(hill: &mut Vec<Vec<SquareVisitor>>) {
+fn reset_visitors(hill: &mut [Vec<SquareVisitor>]) {
     for sv in hill.iter_mut().flatten() {
         sv.visited = false;
     }
@@ -148,4 +327,86 @@ mod tests {
         assert_eq!(neighbour_coords((1, 3), &map), vec![(0, 3), (2, 3), (1, 2)]);
         assert_eq!(neighbour_coords((2, 1), &map), vec![(1, 1), (2, 0), (2, 2)]);
     }
-}
\ No newline at end of file
+
+    const EXAMPLE: &str = include_str!("../example.txt");
+
+    #[test]
+    fn example_distances() {
+        let mut hill = vec![];
+        let (start, end, starts) = initialise_map(EXAMPLE, &mut hill);
+        let distances = distances_to(end, &mut hill);
+        assert_eq!(distances[start.0][start.1], Some(31));
+        assert_eq!(starts.iter().filter_map(|&(i, j)| distances[i][j]).min(), Some(29));
+        assert_eq!(distances[end.0][end.1], Some(0));
+    }
+
+    #[test]
+    fn unreachable_goal() {
+        let mut hill = vec![];
+        let (start, end, _) = initialise_map("SazE\n", &mut hill);
+        let distances = distances_to(end, &mut hill);
+        assert_eq!(distances[start.0][start.1], None);
+        assert_eq!(route(start, &distances, &hill), None);
+        assert_eq!(distances[0][2], Some(1));
+    }
+
+    #[test]
+    fn example_route() {
+        let mut hill = vec![];
+        let (start, end, _) = initialise_map(EXAMPLE, &mut hill);
+        let distances = distances_to(end, &mut hill);
+        let route = route(start, &distances, &hill).unwrap();
+        assert_eq!(route.len(), 32);
+        assert_eq!((route[0], route[31]), (start, end));
+        assert_eq!(draw_route(&route, &hill), "\
+v..v<<<<
+>v.vv<<^
+.v.v>E^^
+.>v>>>^^
+..>>>>>^
+");
+    }
+
+    #[test]
+    fn default_rules_match_bfs() {
+        let mut hill = vec![];
+        let (start, end, starts) = initialise_map(EXAMPLE, &mut hill);
+        let rules = ClimbingRules::default();
+        let (cost, route) = cheapest_hike(&[start], end, &mut hill, &rules).unwrap();
+        assert_eq!(cost, 31);
+        assert_eq!((route.len(), route[0], route[31]), (32, start, end));
+        assert_eq!(cheapest_hike(&starts, end, &mut hill, &rules).unwrap().0, 29);
+    }
+
+    #[test]
+    fn weighted_climbing() {
+        let mut hill = vec![];
+        let (start, end, _) = initialise_map("SbcdE\n", &mut hill);
+        let rules = ClimbingRules{step_cost: Box::new(|c| if c > 0 { 2 } else { 1 }), ..Default::default()};
+        assert_eq!(cheapest_hike(&[start], end, &mut hill, &rules), None);
+        let rules = ClimbingRules{max_ascent: 25, ..rules};
+        assert_eq!(cheapest_hike(&[start], end, &mut hill, &rules).unwrap().0, 8);
+        let rules = ClimbingRules{end_elevation: 'e', ..rules};
+        assert_eq!(cheapest_hike(&[start], end, &mut hill, &rules).unwrap().0, 8);
+        let rules = ClimbingRules{max_descent: 0, ..Default::default()};
+        assert_eq!(cheapest_hike(&[end], start, &mut hill, &rules), None);
+    }
+
+    #[test]
+    fn a_star_matches_dijkstra() {
+        let mut hill = vec![];
+        let (start, end, starts) = initialise_map(include_str!("../input.txt"), &mut hill);
+        for climb in [1, 2, 5] {
+            let rules = ClimbingRules{max_ascent: 2, step_cost: Box::new(move |c| if c > 0 { climb } else { 1 }), ..Default::default()};
+            assert_eq!(rules.cheapest_step(), 1);
+            let (cost, route) = cheapest_hike(&[start], end, &mut hill, &rules).unwrap();
+            assert_eq!(weighted_search(&[start], end, &mut hill, &rules, 0).unwrap().0, cost);
+            let walked: usize = route.windows(2).map(|w| {
+                rules.cost(hill[w[0].0][w[0].1].square.height(), hill[w[1].0][w[1].1].square.height()).unwrap()
+            }).sum();
+            assert_eq!(walked, cost);
+            let (cost, _) = cheapest_hike(&starts, end, &mut hill, &rules).unwrap();
+            assert_eq!(weighted_search(&starts, end, &mut hill, &rules, 0).unwrap().0, cost);
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use std::{fmt::{Display}, cmp::Ordering};
 use std::io;
 use std::io::Read;
+use std::env;
+use std::str::FromStr;
 
-const DIVIDERS: [&str; 2] = ["[[2]]", "[[6]]"];
+/// the divider packets are [[2]] and [[6]]
+const DIVIDERS: [usize; 2] = [2, 6];
 
-#[derive(Debug, PartialEq, Eq, Ord)]
+#[derive(Debug)]
 enum Packet {
-    List(Vec<Box<Packet>>),
+    List(Vec<Packet>),
     Int(usize),
 }
 
@@ -27,95 +30,344 @@ impl Display for Packet {
     }
 }
 
-impl PartialOrd for Packet {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+impl Packet {
+    fn divider(n: usize) -> Self {
+        Packet::List(vec![Packet::List(vec![Packet::Int(n)])])
+    }
+}
+
+impl Ord for Packet {
+    fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
-            (Packet::Int(m), Packet::Int(n)) => m.partial_cmp(n),
-            (Packet::List(v1), Packet::List(v2)) => {
-                for (i, m) in v1.iter().enumerate() {
-                    if v2.len() <= i {
-                        // RHS ran out of items
-                        return Some(Ordering::Greater);
-                    }
-                    match m.partial_cmp(&v2[i]) {
-                        Some(Ordering::Less) => {
-                            return Some(Ordering::Less);
-                        },
-                        Some(Ordering::Greater) => {
-                            return Some(Ordering::Greater);
-                        },
-                        _ => {},
-                    }
-                }
-                if v1.len() == v2.len() {
-                    return Some(Ordering::Equal);
-                }
-                // LHS ran out of items
-                return Some(Ordering::Less);
-            },
-            (Packet::Int(m), Packet::List(_)) => {
-                return Packet::List(vec![Box::new(Packet::Int(*m))]).partial_cmp(other);
-            },
-            (Packet::List(_), Packet::Int(n)) => {
-                return self.partial_cmp(&Packet::List(vec![Box::new(Packet::Int(*n))]));
-            }
+            (Packet::Int(m), Packet::Int(n)) => m.cmp(n),
+            // the shorter list wins if it runs out of items first
+            (Packet::List(v1), Packet::List(v2)) => v1.iter().cmp(v2.iter()),
+            (Packet::Int(_), Packet::List(v)) => cmp_promoted(self, v),
+            (Packet::List(v), Packet::Int(_)) => cmp_promoted(other, v).reverse(),
         }
     }
 }
 
+/// packets are equal when neither comes first, so [[1]] == [1] == 1
+impl PartialEq for Packet {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Packet {}
+
+impl PartialOrd for Packet {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// compares an int with a list as if the int were wrapped in a list of its own,
+/// without building that list
+fn cmp_promoted(int: &Packet, list: &[Packet]) -> Ordering {
+    match list.first() {
+        // RHS ran out of items
+        None => Ordering::Greater,
+        Some(first) => int.cmp(first).then(1.cmp(&list.len())),
+    }
+}
+
 fn main() {
     let mut buf = String::new();
     io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
-    let mut pairs = buf.lines()
-    .collect::<Vec<&str>>()
-    .chunks(3)
-    .map(|pair| (parse(&mut pair[0].chars().skip(1)), parse(&mut pair[1].chars().skip(1))))
-    .map(|(s1, s2)| { let c = s1.partial_cmp(&s2); (s1, s2, c) })
-    .collect::<Vec<(Packet, Packet, Option<Ordering>)>>();
-    let indices = pairs.iter().enumerate().filter(|(_, (_, _, b))| b.unwrap().is_le())
+    let packets = if env::args().any(|arg| arg == "--from-json") {
+        packets_from_json(&buf)
+    } else {
+        PacketParser::new(&buf).collect::<Result<Vec<Packet>, ParseError>>()
+    };
+    let packets = match packets {
+        Ok(packets) if packets.len() % 2 == 0 => packets,
+        Ok(_) => panic!("The last packet has no partner"),
+        Err(e) => panic!("Malformed packet: {:?}", e),
+    };
+    if env::args().any(|arg| arg == "--json") {
+        println!("{}", packets_to_json(&packets));
+        return;
+    }
+    if env::args().any(|arg| arg == "--explain") {
+        for (i, pair) in packets.chunks(2).enumerate() {
+            if let Some(diagnosis) = diagnose(&pair[0], &pair[1]).filter(|d| d.ordering.is_gt()) {
+                println!("Pair {} is out of order", i + 1);
+                println!("{}", side_by_side(&pair[0], &pair[1], &diagnosis));
+            }
+        }
+    }
+    let indices = packets.chunks(2).enumerate().filter(|(_, pair)| pair[0] <= pair[1])
     .map(|(i, _)| i + 1)
     .collect::<Vec<usize>>();
     let indices_sum = indices.iter().sum::<usize>();
     println!("{:?}", indices_sum);
-    let mut packets = pairs.iter_mut()
-    .flat_map(|(p1, p2, _)| vec![p1, p2]).collect::<Vec<&mut Packet>>();
-    let mut dividers = DIVIDERS.iter().map(|d| parse(&mut d.chars().skip(1))).collect::<Vec<Packet>>();
-    packets.append(&mut dividers.iter_mut().collect::<Vec<&mut Packet>>());
-    packets.sort_unstable();
-    let decoder_key = packets.iter().enumerate()
-        .filter(|(_, p)| DIVIDERS.map(|s| s.to_string()).contains(&format!("{}",p)))
-        .map(|(i, _)| i + 1)
-        .product::<usize>();
+    let mut packets = packets;
+    packets.extend(DIVIDERS.map(Packet::divider));
+    let decoder_key = decoder_key(&mut packets);
     println!("Decoder key: {}", decoder_key);
 }
 
-fn parse(input: &mut dyn Iterator<Item = char>) -> Packet {
-    let mut ret = vec![];
-    let mut current = String::new();
-    while let Some(c) = input.next() {
-        match c {
-            '[' => {
-                ret.push(Box::new(parse(input)));
-            },
-            '0'..='9' => {
-                current.push(c);
+/// sorts the packets, which must already include the dividers, and multiplies
+/// the dividers' 1-based positions
+fn decoder_key(packets: &mut [Packet]) -> usize {
+    packets.sort();
+    // the sort is stable and the dividers were added last, so each divider is
+    // the last of any packets equal to it
+    DIVIDERS.map(Packet::divider).iter()
+        .map(|d| packets.partition_point(|p| p <= d))
+        .product::<usize>()
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// what settled the comparison
+#[derive(Debug, PartialEq)]
+enum Decider {
+    Ints{left: usize, right: usize},
+    RanOut(Side),
+}
+
+/// an int on one side was wrapped in a list to compare with a list on the other,
+/// after following `depth` indices of the path
+#[derive(Debug, PartialEq)]
+struct Promotion {
+    depth: usize,
+    side: Side,
+}
+
+/// why a pair of packets compares the way it does
+#[derive(Debug, PartialEq)]
+struct Diagnosis {
+    ordering: Ordering,
+    /// list indices down to the deciding element
+    path: Vec<usize>,
+    decider: Decider,
+    promotions: Vec<Promotion>,
+}
+
+/// explains the first difference between two packets, or None if they're equal
+fn diagnose(left: &Packet, right: &Packet) -> Option<Diagnosis> {
+    let mut diagnosis = Diagnosis{ordering: Ordering::Equal, path: vec![], decider: Decider::RanOut(Side::Left), promotions: vec![]};
+    diagnose_packets(left, right, &mut diagnosis).then_some(diagnosis)
+}
+
+/// fills in the diagnosis and returns true if the packets differ
+fn diagnose_packets(left: &Packet, right: &Packet, diagnosis: &mut Diagnosis) -> bool {
+    match (left, right) {
+        (Packet::Int(m), Packet::Int(n)) => {
+            diagnosis.ordering = m.cmp(n);
+            diagnosis.decider = Decider::Ints{left: *m, right: *n};
+            m != n
+        },
+        (Packet::List(v1), Packet::List(v2)) => diagnose_lists(v1, v2, diagnosis),
+        (Packet::Int(_), Packet::List(v)) => diagnose_promoted(std::slice::from_ref(left), v, Side::Left, diagnosis),
+        (Packet::List(v), Packet::Int(_)) => diagnose_promoted(v, std::slice::from_ref(right), Side::Right, diagnosis),
+    }
+}
+
+fn diagnose_promoted(v1: &[Packet], v2: &[Packet], side: Side, diagnosis: &mut Diagnosis) -> bool {
+    diagnosis.promotions.push(Promotion{depth: diagnosis.path.len(), side});
+    let decided = diagnose_lists(v1, v2, diagnosis);
+    if !decided {
+        diagnosis.promotions.pop();
+    }
+    decided
+}
+
+fn diagnose_lists(v1: &[Packet], v2: &[Packet], diagnosis: &mut Diagnosis) -> bool {
+    for i in 0..v1.len().max(v2.len()) {
+        diagnosis.path.push(i);
+        let decided = match (v1.get(i), v2.get(i)) {
+            (Some(m), Some(n)) => diagnose_packets(m, n, diagnosis),
+            (None, _) => {
+                diagnosis.ordering = Ordering::Less;
+                diagnosis.decider = Decider::RanOut(Side::Left);
+                true
             },
-            ',' => {
-                if current.chars().count() > 0 {
-                    ret.push(Box::new(Packet::Int(current.parse::<usize>().unwrap())));
-                    current = String::new();
-                }
+            (_, None) => {
+                diagnosis.ordering = Ordering::Greater;
+                diagnosis.decider = Decider::RanOut(Side::Right);
+                true
             },
-            ']' => {
-                if current.chars().count() > 0 {
-                    ret.push(Box::new(Packet::Int(current.parse::<usize>().unwrap())));
-                }
-                return Packet::List(ret);
+        };
+        if decided {
+            return true;
+        }
+        diagnosis.path.pop();
+    }
+    false
+}
+
+/// follows the path down both packets a level at a time, showing the two
+/// elements compared at each level and then the reason for the decision
+fn side_by_side(left: &Packet, right: &Packet, diagnosis: &Diagnosis) -> String {
+    fn child(p: Option<&Packet>, i: usize) -> Option<&Packet> {
+        match p {
+            Some(Packet::List(v)) => v.get(i),
+            // a promoted int is the only item of its list
+            Some(Packet::Int(_)) => p,
+            None => None,
+        }
+    }
+    let show = |p: Option<&Packet>, depth: usize, side: Side| match p {
+        Some(p) if diagnosis.promotions.contains(&Promotion{depth, side}) => format!("{} as [{}]", p, p),
+        Some(p) => p.to_string(),
+        None => "(ran out)".to_string(),
+    };
+    let mut rows = vec![("path".to_string(), "left".to_string(), "right".to_string())];
+    let (mut l, mut r) = (Some(left), Some(right));
+    for depth in 0..=diagnosis.path.len() {
+        if depth > 0 {
+            let i = diagnosis.path[depth - 1];
+            (l, r) = (child(l, i), child(r, i));
+        }
+        rows.push((format!("{:?}", &diagnosis.path[..depth]), show(l, depth, Side::Left), show(r, depth, Side::Right)));
+    }
+    let path_width = rows.iter().map(|r| r.0.len()).max().unwrap();
+    let left_width = rows.iter().map(|r| r.1.len()).max().unwrap();
+    let mut output = rows.iter()
+        .map(|(p, l, r)| format!("{:path_width$}  {:left_width$}  {}", p, l, r).trim_end().to_string() + "\n")
+        .collect::<String>();
+    let order = if diagnosis.ordering.is_lt() { "in the right order" } else { "not in the right order" };
+    output += &match diagnosis.decider {
+        Decider::Ints{left, right} => format!("{} {} {}, so the packets are {}", left, if left < right { '<' } else { '>' }, right, order),
+        Decider::RanOut(Side::Left) => format!("left ran out of items first, so the packets are {}", order),
+        Decider::RanOut(Side::Right) => format!("right ran out of items first, so the packets are {}", order),
+    };
+    output
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    Unexpected{offset: usize, found: char, expected: &'static str},
+    UnexpectedEnd{offset: usize, expected: &'static str},
+    NumberTooLarge{offset: usize},
+    TrailingInput{offset: usize},
+}
+
+/// reads packets one after another from the input, separated by any whitespace
+struct PacketParser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl<'a> PacketParser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self{input, offset: 0}
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.offset += c.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, expected: &'static str, valid: impl Fn(char) -> bool) -> Result<char, ParseError> {
+        match self.peek() {
+            Some(c) if valid(c) => {
+                self.offset += c.len_utf8();
+                Ok(c)
             },
-            _ => unreachable!(),
+            Some(found) => Err(ParseError::Unexpected{offset: self.offset, found, expected}),
+            None => Err(ParseError::UnexpectedEnd{offset: self.offset, expected}),
+        }
+    }
+
+    /// a bracketed, comma-separated sequence of whatever `item` reads
+    fn items(&mut self, item: fn(&mut Self) -> Result<Packet, ParseError>) -> Result<Vec<Packet>, ParseError> {
+        self.expect("'['", |c| c == '[')?;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.offset += 1;
+            return Ok(items);
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(item(self)?);
+            self.skip_whitespace();
+            if self.expect("',' or ']'", |c| c == ',' || c == ']')? == ']' {
+                return Ok(items);
+            }
+        }
+    }
+
+    fn list(&mut self) -> Result<Packet, ParseError> {
+        self.items(Self::value).map(Packet::List)
+    }
+
+    /// only whitespace may follow
+    fn finish(&mut self) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        match self.offset {
+            offset if offset < self.input.len() => Err(ParseError::TrailingInput{offset}),
+            _ => Ok(()),
+        }
+    }
+
+    fn value(&mut self) -> Result<Packet, ParseError> {
+        if self.peek() == Some('[') {
+            return self.list();
         }
+        let start = self.offset;
+        self.expect("a number or '['", |c| c.is_ascii_digit())?;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.offset += 1;
+        }
+        self.input[start..self.offset].parse::<usize>()
+            .map(Packet::Int)
+            .map_err(|_| ParseError::NumberTooLarge{offset: start})
     }
-    unreachable!()
+}
+
+impl Iterator for PacketParser<'_> {
+    type Item = Result<Packet, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_whitespace();
+        self.peek()?;
+        let packet = self.list();
+        if packet.is_err() {
+            // stop at the first error rather than resynchronising
+            self.offset = self.input.len();
+        }
+        Some(packet)
+    }
+}
+
+impl FromStr for Packet {
+    type Err = ParseError;
+
+    /// a single packet, which may be spread over several lines
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = PacketParser::new(s);
+        parser.skip_whitespace();
+        let packet = parser.list()?;
+        parser.finish().map(|_| packet)
+    }
+}
+
+/// packets are already valid JSON, so this is the same as Display
+fn packets_to_json(packets: &[Packet]) -> String {
+    format!("[{}]", packets.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(","))
+}
+
+/// reads a JSON array of packets
+fn packets_from_json(json: &str) -> Result<Vec<Packet>, ParseError> {
+    let mut parser = PacketParser::new(json);
+    parser.skip_whitespace();
+    let packets = parser.items(PacketParser::list)?;
+    parser.finish().map(|_| packets)
 }
 
 #[cfg(test)]
@@ -125,11 +377,11 @@ mod tests {
     #[test]
     fn display() {
         let input = Packet::List(vec![
-            Box::new(Packet::Int(1)),
-            Box::new(Packet::List(vec![
-                Box::new(Packet::Int(2)),
-                Box::new(Packet::Int(3)),
-            ])),
+            Packet::Int(1),
+            Packet::List(vec![
+                Packet::Int(2),
+                Packet::Int(3),
+            ]),
         ]);
         assert_eq!(format!("{}", input), "[1,[2,3]]");
     }
@@ -137,84 +389,221 @@ mod tests {
     #[test]
     fn parse_test() {
         let input = "[1,[2,3]]";
-        let packet = parse(&mut input.chars().skip(1));
+        let packet = input.parse::<Packet>().unwrap();
         let expected = Packet::List(vec![
-            Box::new(Packet::Int(1)),
-            Box::new(Packet::List(vec![
-                Box::new(Packet::Int(2)),
-                Box::new(Packet::Int(3)),
-            ])),
+            Packet::Int(1),
+            Packet::List(vec![
+                Packet::Int(2),
+                Packet::Int(3),
+            ]),
         ]);
-        assert_eq!(packet, expected);
+        // == ignores nesting, so compare the rendered structure instead
+        assert_eq!(packet.to_string(), expected.to_string());
     }
 
     #[test]
     fn pair1() {
         let one = "[1,1,3,1,1]";
         let two = "[1,1,5,1,1]";
-        assert!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1)));
+        assert!(one.parse::<Packet>().unwrap() < two.parse::<Packet>().unwrap());
     }
 
     #[test]
     fn pair2() {
         let one = "[[1],[2,3,4]]";
         let two = "[[1],4]";
-        assert!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1)));
+        assert!(one.parse::<Packet>().unwrap() < two.parse::<Packet>().unwrap());
     }
 
     #[test]
     fn pair3() {
         let one = "[9]";
         let two = "[[8,7,6]]";
-        assert!(!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1))));
+        assert!(one.parse::<Packet>().unwrap() >= two.parse::<Packet>().unwrap());
     }
 
     #[test]
     fn pair4() {
         let one = "[[4,4],4,4]";
         let two = "[[4,4],4,4,4]";
-        assert!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1)));
+        assert!(one.parse::<Packet>().unwrap() < two.parse::<Packet>().unwrap());
     }
 
     #[test]
     fn pair5() {
         let one = "[7,7,7,7]";
         let two = "[7,7,7]";
-        assert!(!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1))));
+        assert!(one.parse::<Packet>().unwrap() >= two.parse::<Packet>().unwrap());
     }
 
     #[test]
     fn pair6() {
         let one = "[]";
         let two = "[3]";
-        assert!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1)));
+        assert!(one.parse::<Packet>().unwrap() < two.parse::<Packet>().unwrap());
     }
 
     #[test]
     fn pair7() {
         let one = "[[[]]]";
         let two = "[[]]";
-        assert!(!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1))));
+        assert!(one.parse::<Packet>().unwrap() >= two.parse::<Packet>().unwrap());
     }
 
     #[test]
     fn pair8() {
         let one = "[1,[2,[3,[4,[5,6,7]]]],8,9]";
         let two = "[1,[2,[3,[4,[5,6,0]]]],8,9]";
-        assert!(!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1))));
+        assert!(one.parse::<Packet>().unwrap() >= two.parse::<Packet>().unwrap());
     }
 
     #[test]
     fn equality() {
         let one = "[1,[2,[3,[4,[5,6,7]]]],8,9]";
         let two = "[1,[2,[3,[4,[5,6,7]]]],8,9]";
-        assert!(!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1))));
+        assert!(one.parse::<Packet>().unwrap() >= two.parse::<Packet>().unwrap());
     }
 
     #[test]
     fn faux_equality() {
         let one = "[1,[2],3]";
         let two = "[1,2,1]";
-        assert!(!(parse(&mut one.chars().skip(1)) < parse(&mut two.chars().skip(1))));
+        assert!(one.parse::<Packet>().unwrap() >= two.parse::<Packet>().unwrap());
+    }
+
+    #[test]
+    fn promotion() {
+        let packet = |s: &str| s.parse::<Packet>().unwrap();
+        assert_eq!(Packet::Int(3).cmp(&packet("[3]")), Ordering::Equal);
+        assert_eq!(Packet::Int(3).cmp(&packet("[[[3]]]")), Ordering::Equal);
+        assert_eq!(Packet::Int(3).cmp(&packet("[3,0]")), Ordering::Less);
+        assert_eq!(Packet::Int(3).cmp(&packet("[]")), Ordering::Greater);
+        assert_eq!(packet("[[]]").cmp(&Packet::Int(0)), Ordering::Less);
+        assert_eq!(packet("[[4],2]").cmp(&packet("[4,[2]]")), Ordering::Equal);
+        assert_eq!(packet("[[4],2]"), packet("[4,[2]]"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn decoder_key_example() {
+        let mut packets = include_str!("../example.txt").lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| l.parse::<Packet>().unwrap())
+            .chain(DIVIDERS.map(Packet::divider))
+            .collect::<Vec<Packet>>();
+        assert_eq!(decoder_key(&mut packets), 140);
+        assert_eq!(format!("{}", packets[9]), "[[2]]");
+    }
+
+    #[test]
+    fn decoder_key_with_equal_packets() {
+        // [2] and 2-in-a-list packets compare equal to the [[2]] divider
+        let mut packets = ["[[6]]", "[2]", "[[[2]]]", "[1]", "[7]"].iter()
+            .map(|l| l.parse::<Packet>().unwrap())
+            .chain(DIVIDERS.map(Packet::divider))
+            .collect::<Vec<Packet>>();
+        assert_eq!(decoder_key(&mut packets), 4 * 6);
+        let sorted = packets.iter().map(|p| p.to_string()).collect::<Vec<String>>();
+        assert_eq!(sorted, ["[1]", "[2]", "[[[2]]]", "[[2]]", "[[6]]", "[[6]]", "[7]"]);
+    }
+
+    #[test]
+    fn any_whitespace() {
+        let packets = PacketParser::new("\n[1, [2]]\t[3]\n\n\n[ ]  [[4\n,5]]")
+            .collect::<Result<Vec<Packet>, ParseError>>().unwrap();
+        let packets = packets.iter().map(|p| p.to_string()).collect::<Vec<String>>();
+        assert_eq!(packets, ["[1,[2]]", "[3]", "[]", "[[4,5]]"]);
+        let example = include_str!("../example.txt");
+        assert_eq!(PacketParser::new(example).count(), 16);
+    }
+
+    #[test]
+    fn malformed_packets() {
+        let error = |s: &str| PacketParser::new(s).find_map(Result::err);
+        assert_eq!(error("[1,2]\n[1,[2]"), Some(ParseError::UnexpectedEnd{offset: 12, expected: "',' or ']'"}));
+        assert_eq!(error("[1,2]]"), Some(ParseError::Unexpected{offset: 5, found: ']', expected: "'['"}));
+        assert_eq!(error("[1,a]"), Some(ParseError::Unexpected{offset: 3, found: 'a', expected: "a number or '['"}));
+        assert_eq!(error("[1,,2]"), Some(ParseError::Unexpected{offset: 3, found: ',', expected: "a number or '['"}));
+        assert_eq!(error("[1 2]"), Some(ParseError::Unexpected{offset: 3, found: '2', expected: "',' or ']'"}));
+        assert_eq!(error("[-1]"), Some(ParseError::Unexpected{offset: 1, found: '-', expected: "a number or '['"}));
+        assert_eq!(error("[é,1]"), Some(ParseError::Unexpected{offset: 1, found: 'é', expected: "a number or '['"}));
+        assert_eq!(error("[1]\n[99999999999999999999999]"), Some(ParseError::NumberTooLarge{offset: 5}));
+        assert_eq!("[1] [2]".parse::<Packet>(), Err(ParseError::TrailingInput{offset: 4}));
+        assert_eq!(PacketParser::new("[1] [").count(), 2);
+    }
+
+    #[test]
+    fn json() {
+        let packets = PacketParser::new(include_str!("../example.txt"))
+            .collect::<Result<Vec<Packet>, ParseError>>().unwrap();
+        let json = packets_to_json(&packets);
+        assert!(json.starts_with("[[1,1,3,1,1],[1,1,5,1,1],[[1],[2,3,4]],"));
+        let round_trip = packets_from_json(&json).unwrap();
+        assert_eq!(round_trip.len(), 16);
+        assert!(round_trip.iter().zip(&packets).all(|(a, b)| a.to_string() == b.to_string()));
+        let pretty = "[\n  [1, [2, 3]],\n  []\n]\n";
+        assert_eq!(packets_to_json(&packets_from_json(pretty).unwrap()), "[[1,[2,3]],[]]");
+        assert_eq!(packets_from_json("[[1], 2]"), Err(ParseError::Unexpected{offset: 6, found: '2', expected: "'['"}));
+    }
+
+    #[test]
+    fn diagnose_example_pairs() {
+        let packets = PacketParser::new(include_str!("../example.txt"))
+            .collect::<Result<Vec<Packet>, ParseError>>().unwrap();
+        let diagnoses = packets.chunks(2).map(|pair| diagnose(&pair[0], &pair[1]).unwrap()).collect::<Vec<Diagnosis>>();
+        assert_eq!(diagnoses[0], Diagnosis{ordering: Ordering::Less, path: vec![2], decider: Decider::Ints{left: 3, right: 5}, promotions: vec![]});
+        assert_eq!(diagnoses[1], Diagnosis{
+            ordering: Ordering::Less,
+            path: vec![1, 0],
+            decider: Decider::Ints{left: 2, right: 4},
+            promotions: vec![Promotion{depth: 1, side: Side::Right}],
+        });
+        assert_eq!(diagnoses[2].path, [0, 0]);
+        assert_eq!(diagnoses[2].promotions, [Promotion{depth: 1, side: Side::Left}]);
+        assert_eq!((diagnoses[4].path.clone(), &diagnoses[4].decider), (vec![3], &Decider::RanOut(Side::Right)));
+        assert_eq!(diagnoses[5].decider, Decider::RanOut(Side::Left));
+        assert_eq!((diagnoses[6].path.clone(), &diagnoses[6].decider), (vec![0, 0], &Decider::RanOut(Side::Right)));
+        assert_eq!(diagnoses[7].path, [1, 1, 1, 1, 2]);
+        assert_eq!(diagnose(&packets[0], &packets[0]), None);
+    }
+
+    #[test]
+    fn nested_promotions() {
+        let diagnosis = diagnose(&"[9]".parse().unwrap(), &"[[[8]]]".parse().unwrap()).unwrap();
+        assert_eq!(diagnosis.path, [0, 0, 0]);
+        assert_eq!(diagnosis.promotions, [Promotion{depth: 1, side: Side::Left}, Promotion{depth: 2, side: Side::Left}]);
+        // a promotion that didn't decide anything is dropped
+        let diagnosis = diagnose(&"[[1],2]".parse().unwrap(), &"[1,3]".parse().unwrap()).unwrap();
+        assert_eq!((diagnosis.path, diagnosis.promotions), (vec![1], vec![]));
+    }
+
+    #[test]
+    fn diagnosis_agrees_with_ord() {
+        let packets = PacketParser::new(include_str!("../input.txt"))
+            .collect::<Result<Vec<Packet>, ParseError>>().unwrap();
+        for left in &packets {
+            for right in packets.iter().step_by(7) {
+                let ordering = diagnose(left, right).map_or(Ordering::Equal, |d| d.ordering);
+                assert_eq!(ordering, left.cmp(right));
+            }
+        }
+    }
+
+    #[test]
+    fn render_side_by_side() {
+        let (left, right) = ("[[1],[2,3,4]]".parse().unwrap(), "[[1],4]".parse().unwrap());
+        let rendered = side_by_side(&left, &right, &diagnose(&left, &right).unwrap());
+        assert_eq!(rendered, "\
+path    left           right
+[]      [[1],[2,3,4]]  [[1],4]
+[1]     [2,3,4]        4 as [4]
+[1, 0]  2              4
+2 < 4, so the packets are in the right order");
+        let (left, right) = ("[7,7,7,7]".parse().unwrap(), "[7,7,7]".parse().unwrap());
+        let rendered = side_by_side(&left, &right, &diagnose(&left, &right).unwrap());
+        assert_eq!(rendered, "\
+path  left       right
+[]    [7,7,7,7]  [7,7,7]
+[3]   7          (ran out)
+right ran out of items first, so the packets are not in the right order");
+    }
+}
@@ -1,27 +1,337 @@
+#[cfg(test)]
 use std::cmp::{min,max};
+use std::collections::HashMap;
 use std::io;
 use std::io::Read;
+use std::env;
 
+#[cfg(test)]
 const START: (usize, usize) = (500, 0);
 
+type Point = (i64, i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tile {
+    Rock,
+    Sand,
+    Falling,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Rested(Point),
+    /// there's no floor and nothing below to stop the grain
+    IntoAbyss,
+    /// every source is buried in sand
+    Blocked,
+}
+
+/// one straight run of rock in a scan path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    from: Point,
+    to: Point,
+}
+
+impl Segment {
+    fn points(&self) -> impl Iterator<Item = Point> {
+        let (dx, dy) = ((self.to.0 - self.from.0).signum(), (self.to.1 - self.from.1).signum());
+        let length = (self.to.0 - self.from.0).abs().max((self.to.1 - self.from.1).abs());
+        let from = self.from;
+        (0..=length).map(move |i| (from.0 + i * dx, from.1 + i * dy))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ScanError {
+    BadPoint{line: usize, text: String},
+    TooFewPoints{line: usize},
+    Diagonal{line: usize, segment: Segment},
+    ZeroLength{line: usize, point: Point},
+}
+
+/// reads each line of the scan as a path of segments
+fn parse_scan(input: &str) -> Result<Vec<Vec<Segment>>, ScanError> {
+    let mut paths = vec![];
+    for (line, text) in input.lines().enumerate().map(|(n, l)| (n + 1, l.trim())).filter(|(_, l)| !l.is_empty()) {
+        let points = text.split("->").map(|point| {
+            let point = point.trim();
+            point.split_once(',')
+                .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+                .ok_or(ScanError::BadPoint{line, text: point.to_string()})
+        }).collect::<Result<Vec<Point>, ScanError>>()?;
+        if points.len() < 2 {
+            return Err(ScanError::TooFewPoints{line});
+        }
+        let path = points.windows(2).map(|w| {
+            let segment = Segment{from: w[0], to: w[1]};
+            match (w[0].0 == w[1].0, w[0].1 == w[1].1) {
+                (true, true) => Err(ScanError::ZeroLength{line, point: w[0]}),
+                (false, false) => Err(ScanError::Diagonal{line, segment}),
+                _ => Ok(segment),
+            }
+        }).collect::<Result<Vec<Segment>, ScanError>>()?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// a sparse cave, so rock and sand can be anywhere
+struct Cave {
+    tiles: HashMap<Point, Tile>,
+    sources: Vec<Point>,
+    /// the y of the infinite floor, if there is one
+    floor: Option<i64>,
+    lowest_rock: i64,
+    next_source: usize,
+    falling: Option<Point>,
+    /// where the last grain from each source fell, so the next can resume there
+    paths: Vec<Vec<Point>>,
+    /// which paths go through each point, and how far along them
+    on_path: HashMap<Point, Vec<(usize, usize)>>,
+}
+
+impl Cave {
+    fn new(rocks: impl IntoIterator<Item = Point>, sources: Vec<Point>) -> Self {
+        let tiles = rocks.into_iter().map(|p| (p, Tile::Rock)).collect::<HashMap<Point, Tile>>();
+        let lowest_rock = tiles.keys().map(|p| p.1).max().unwrap_or(0);
+        let paths = vec![vec![]; sources.len()];
+        Self{tiles, sources, floor: None, lowest_rock, next_source: 0, falling: None, paths, on_path: HashMap::new()}
+    }
+
+    fn from_scan(paths: &[Vec<Segment>], sources: Vec<Point>) -> Self {
+        Self::new(paths.iter().flatten().flat_map(Segment::points), sources)
+    }
+
+    /// puts the floor this far below the lowest rock
+    fn add_floor(&mut self, gap: i64) {
+        self.floor = Some(self.lowest_rock + gap);
+        self.paths.iter_mut().for_each(Vec::clear);
+        self.on_path.clear();
+    }
+
+    fn tile(&self, point: Point) -> Option<Tile> {
+        match self.floor {
+            Some(y) if point.1 >= y => Some(Tile::Rock),
+            _ => self.tiles.get(&point).copied(),
+        }
+    }
+
+    /// moves the falling grain one square, or starts a new one from the next
+    /// unblocked source, returning what happened if the grain stopped
+    fn step(&mut self) -> Option<Outcome> {
+        let Some((x, y)) = self.falling else {
+            let Some(i) = self.next_free_source() else {
+                return Some(Outcome::Blocked);
+            };
+            self.falling = Some(self.sources[i]);
+            self.tiles.insert(self.sources[i], Tile::Falling);
+            return None;
+        };
+        match self.next_position((x, y)) {
+            Some(p) => {
+                self.tiles.remove(&(x, y));
+                if self.floor.is_none() && p.1 > self.lowest_rock {
+                    self.falling = None;
+                    return Some(Outcome::IntoAbyss);
+                }
+                self.tiles.insert(p, Tile::Falling);
+                self.falling = Some(p);
+                None
+            },
+            None => {
+                self.falling = None;
+                self.settle((x, y), None);
+                Some(Outcome::Rested((x, y)))
+            },
+        }
+    }
+
+    /// the sources take turns, skipping any that are blocked
+    fn next_free_source(&mut self) -> Option<usize> {
+        let i = (0..self.sources.len())
+            .map(|i| (self.next_source + i) % self.sources.len())
+            .find(|&i| self.tile(self.sources[i]).is_none())?;
+        self.next_source = (i + 1) % self.sources.len();
+        Some(i)
+    }
+
+    fn next_position(&self, (x, y): Point) -> Option<Point> {
+        [(x, y + 1), (x - 1, y + 1), (x + 1, y + 1)].into_iter().find(|&p| self.tile(p).is_none())
+    }
+
+    /// leaves a grain at rest, cutting short any path that went through it
+    /// other than that of the source it came from
+    fn settle(&mut self, point: Point, source: Option<usize>) {
+        self.tiles.insert(point, Tile::Sand);
+        let crossing = self.on_path.get(&point).cloned().unwrap_or_default();
+        for (s, i) in crossing.into_iter().filter(|&(s, _)| Some(s) != source) {
+            self.truncate_path(s, i);
+        }
+    }
+
+    fn push_path(&mut self, source: usize, point: Point) {
+        self.on_path.entry(point).or_default().push((source, self.paths[source].len()));
+        self.paths[source].push(point);
+    }
+
+    fn truncate_path(&mut self, source: usize, len: usize) {
+        for point in self.paths[source].drain(len..) {
+            let crossing = self.on_path.get_mut(&point).unwrap();
+            crossing.retain(|&(s, _)| s != source);
+            if crossing.is_empty() {
+                self.on_path.remove(&point);
+            }
+        }
+    }
+
+    /// drops a grain from where the last one from the same source was just
+    /// before it came to rest, rather than from the source
+    fn drop_along_path(&mut self) -> Outcome {
+        let Some(i) = self.next_free_source() else {
+            return Outcome::Blocked;
+        };
+        if self.paths[i].is_empty() {
+            self.push_path(i, self.sources[i]);
+        }
+        loop {
+            let current = *self.paths[i].last().unwrap();
+            match self.next_position(current) {
+                Some(p) if self.floor.is_none() && p.1 > self.lowest_rock => return Outcome::IntoAbyss,
+                Some(p) => self.push_path(i, p),
+                None => {
+                    self.truncate_path(i, self.paths[i].len() - 1);
+                    self.settle(current, Some(i));
+                    return Outcome::Rested(current);
+                },
+            }
+        }
+    }
+
+    /// the sand there'll be once the floor is full, from the rock alone: a
+    /// square is filled if it's not rock and one of the three above it is
+    fn flood(&self) -> Option<usize> {
+        let floor = self.floor?;
+        let top = self.sources.iter().map(|s| s.1).min()?;
+        let left = self.sources.iter().map(|s| s.0).min()? - (floor - top);
+        let right = self.sources.iter().map(|s| s.0).max()? + (floor - top);
+        let width = (right - left + 1) as usize;
+        let mut above = vec![false; width + 2];
+        let mut count = 0;
+        for y in top..floor {
+            let mut row = vec![false; width + 2];
+            // row and above have a spare square at each end
+            for i in 1..=width {
+                let x = left + i as i64 - 1;
+                let source = self.sources.contains(&(x, y));
+                let fed = above[i - 1] || above[i] || above[i + 1];
+                row[i] = (source || fed) && self.tiles.get(&(x, y)) != Some(&Tile::Rock);
+            }
+            count += row.iter().filter(|&&f| f).count();
+            above = row;
+        }
+        Some(count)
+    }
+
+    fn drop_grain(&mut self) -> Outcome {
+        loop {
+            if let Some(outcome) = self.step() {
+                return outcome;
+            }
+        }
+    }
+
+    /// drops grains until one falls into the abyss or the sources are blocked,
+    /// returning the sand at rest
+    fn fill(&mut self) -> usize {
+        while let Outcome::Rested(_) = self.drop_along_path() {}
+        self.sand()
+    }
+
+    /// the same as fill, but every grain falls all the way from its source
+    fn fill_by_steps(&mut self) -> usize {
+        while let Outcome::Rested(_) = self.drop_grain() {}
+        self.sand()
+    }
+
+    fn sand(&self) -> usize {
+        self.tiles.values().filter(|&&t| t == Tile::Sand).count()
+    }
+
+    /// draws everything from the sources down to the floor, or the lowest
+    /// rock if there isn't one
+    fn render(&self) -> String {
+        let points = self.tiles.keys().chain(&self.sources);
+        let left = points.clone().map(|p| p.0).min().unwrap_or(0);
+        let right = points.clone().map(|p| p.0).max().unwrap_or(0);
+        let top = points.map(|p| p.1).min().unwrap_or(0);
+        let bottom = self.floor.unwrap_or(self.lowest_rock);
+        let mut output = String::new();
+        for y in top..=bottom {
+            for x in left..=right {
+                output.push(match self.tile((x, y)) {
+                    Some(Tile::Rock) => '#',
+                    Some(Tile::Sand) => 'o',
+                    Some(Tile::Falling) => '~',
+                    None if self.sources.contains(&(x, y)) => '+',
+                    None => '.',
+                });
+            }
+            output.push('\n');
+        }
+        output
+    }
+}
+
 fn main() {
     let mut buf = String::new();
     io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
-    let points = parse_input(buf);
-    let mut space = space(&points);
-    let mut sand_count = 0;
-    while !sand_falls_to_floor(START, &mut space) {
-        sand_count += 1;
+    let paths = match parse_scan(&buf) {
+        Ok(paths) => paths,
+        Err(e) => panic!("Bad scan: {:?}", e),
+    };
+    let mut sources = vec![];
+    let mut floor_gap = 2;
+    let mut flood = false;
+    let mut steps = false;
+    let mut draw = false;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().expect("Expected a value");
+        match arg.as_str() {
+            "--flood" => flood = true,
+            "--steps" => steps = true,
+            "--draw" => draw = true,
+            "--source" => {
+                let (x, y) = value().split_once(',').expect("Expected x,y");
+                sources.push((x.parse().expect("Bad x"), y.parse().expect("Bad y")));
+            },
+            "--floor-gap" => floor_gap = value().parse().expect("Bad floor gap"),
+            _ => panic!("Unknown option {arg}"),
+        }
     }
-    println!("{sand_count} units of sand fell");
-    sand_count += 1;
-    while !sand_filled(START, &mut space) {
-        sand_count += 1;
+    if sources.is_empty() {
+        sources.push((500, 0));
+    }
+    let mut cave = Cave::from_scan(&paths, sources);
+    if draw {
+        print!("{}", cave.render());
     }
-    sand_count += 1;
+    let fill = if steps { Cave::fill_by_steps } else { Cave::fill };
+    let sand_count = fill(&mut cave);
     println!("{sand_count} units of sand fell");
+    cave.add_floor(floor_gap);
+    let sand_count = if flood { cave.flood().unwrap() } else { fill(&mut cave) };
+    println!("{sand_count} units of sand fell");
+    if draw {
+        // flooding only counts the sand, so with --flood this shows part one's
+        // sand above the floor
+        print!("{}", cave.render());
+    }
 }
 
+#[cfg(test)]
 fn parse_input(input: String) -> Vec<(usize, usize)> {
     let mut ret = vec![];
     for line in input.lines() {
@@ -45,7 +355,8 @@ fn parse_input(input: String) -> Vec<(usize, usize)> {
     ret
 }
 
-fn sand_falls_to_floor(start: (usize, usize), space: &mut Vec<Vec<bool>>) -> bool {
+#[cfg(test)]
+fn sand_falls_to_floor(start: (usize, usize), space: &mut [Vec<bool>]) -> bool {
     let mut sand_position = start;
     while move_sand_down(&mut sand_position, space) {
         if sand_position.1 + 1 >= space.len() {
@@ -56,6 +367,7 @@ fn sand_falls_to_floor(start: (usize, usize), space: &mut Vec<Vec<bool>>) -> boo
     sand_position.1 >= space.len() - 3
 }
 
+#[cfg(test)]
 fn sand_filled(start: (usize, usize), space: &mut [Vec<bool>]) -> bool {
     let mut sand_position = start;
     while move_sand_down(&mut sand_position, space) {
@@ -64,6 +376,7 @@ fn sand_filled(start: (usize, usize), space: &mut [Vec<bool>]) -> bool {
     sand_position == START
 }
 
+#[cfg(test)]
 fn move_sand_down(position: &mut (usize, usize), space: &[Vec<bool>]) -> bool {
     if !space[position.1 + 1][position.0] {
         *position = (position.0, position.1 + 1);
@@ -80,6 +393,7 @@ fn move_sand_down(position: &mut (usize, usize), space: &[Vec<bool>]) -> bool {
     false
 }
 
+#[cfg(test)]
 fn space(points: &[(usize, usize)]) -> Vec<Vec<bool>> {
     let mut pts = points.to_owned();
     pts.push((500, 0));
@@ -208,4 +522,201 @@ mod tests {
         assert!(result);
         assert!(space[2][0]);
     }
+
+    fn example_cave() -> Cave {
+        Cave::from_scan(&parse_scan(include_str!("../example.txt")).unwrap(), vec![(500, 0)])
+    }
+
+    #[test]
+    fn example() {
+        let mut cave = example_cave();
+        assert_eq!(cave.fill(), 24);
+        cave.add_floor(2);
+        assert_eq!(cave.fill(), 93);
+        assert_eq!(cave.tile((500, 0)), Some(Tile::Sand));
+        assert_eq!(cave.drop_grain(), Outcome::Blocked);
+    }
+
+    #[test]
+    fn matches_step_simulation() {
+        let input = include_str!("../input.txt").to_string();
+        let points = parse_input(input);
+        let mut space = space(&points);
+        let mut expected = 0;
+        while !sand_falls_to_floor(START, &mut space) {
+            expected += 1;
+        }
+        let mut cave = Cave::new(points.iter().map(|&(x, y)| (x as i64, y as i64)), vec![(500, 0)]);
+        assert_eq!(cave.fill(), expected);
+        expected += 1;
+        while !sand_filled(START, &mut space) {
+            expected += 1;
+        }
+        cave.add_floor(2);
+        assert_eq!(cave.flood(), Some(expected + 1));
+        assert_eq!(cave.fill(), expected + 1);
+    }
+
+    #[test]
+    fn falling_grain() {
+        let mut cave = example_cave();
+        assert_eq!(cave.step(), None);
+        assert_eq!(cave.tile((500, 0)), Some(Tile::Falling));
+        for _ in 0..3 {
+            assert_eq!(cave.step(), None);
+        }
+        assert_eq!(cave.tile((500, 0)), None);
+        assert_eq!(cave.tile((500, 3)), Some(Tile::Falling));
+        assert_eq!(cave.drop_grain(), Outcome::Rested((500, 8)));
+        assert_eq!(cave.tile((500, 8)), Some(Tile::Sand));
+        assert_eq!(cave.tiles.values().filter(|&&t| t == Tile::Falling).count(), 0);
+    }
+
+    #[test]
+    fn rock_anywhere() {
+        // a cup straddling x=0 with a source above it, and another far to the right
+        let cup = |x: i64| [(x - 2, 3), (x - 2, 4), (x - 1, 4), (x, 4), (x + 1, 4), (x + 2, 4), (x + 2, 3)];
+        let mut cave = Cave::new(cup(0).into_iter().chain(cup(5000)), vec![(0, 0), (5000, 0)]);
+        let outcomes = (0..6).map(|_| cave.drop_grain()).collect::<Vec<Outcome>>();
+        assert_eq!(outcomes, [(0, 3), (5000, 3), (-1, 3), (4999, 3), (1, 3), (5001, 3)].map(Outcome::Rested));
+        assert_eq!(cave.drop_grain(), Outcome::Rested((0, 2)));
+        assert!(cave.fill() > 7);
+    }
+
+    #[test]
+    fn floor_depth() {
+        let mut cave = Cave::new([(0, 3)], vec![(0, 0)]);
+        cave.add_floor(2);
+        // a 5-deep triangle less the rock
+        assert_eq!(cave.fill(), 25 - 1);
+        let mut cave = Cave::new([(0, 3)], vec![(0, 0), (20, 0)]);
+        cave.add_floor(2);
+        assert_eq!(cave.fill(), 24 + 25);
+        let mut cave = Cave::new([(0, 3)], vec![(0, 0)]);
+        cave.add_floor(1);
+        assert_eq!(cave.fill(), 16 - 1);
+    }
+
+    #[test]
+    fn memoised_matches_steps() {
+        let mut memoised = example_cave();
+        let mut stepped = example_cave();
+        loop {
+            let outcome = memoised.drop_along_path();
+            assert_eq!(outcome, stepped.drop_grain());
+            match outcome {
+                Outcome::Rested(_) => {},
+                Outcome::IntoAbyss => {
+                    memoised.add_floor(2);
+                    stepped.add_floor(2);
+                },
+                Outcome::Blocked => break,
+            }
+        }
+        assert_eq!(memoised.sand(), 93);
+    }
+
+    #[test]
+    fn flood_example() {
+        let mut cave = example_cave();
+        cave.add_floor(2);
+        assert_eq!(cave.flood(), Some(93));
+        let mut cave = Cave::new([(0, 3)], vec![(0, 0), (20, 0)]);
+        cave.add_floor(2);
+        assert_eq!(cave.flood(), Some(24 + 25));
+        // overlapping triangles
+        let mut cave = Cave::new([(3, 2), (4, 2), (5, 2)], vec![(0, 0), (8, 1)]);
+        cave.add_floor(4);
+        let flood = cave.flood();
+        assert_eq!(flood, Some(cave.fill()));
+    }
+
+    /// a deep cave of staggered shelves
+    fn deep_cave(depth: i64) -> Cave {
+        let mut rocks = vec![];
+        for y in (4..depth).step_by(4) {
+            let x = 500 + (y * 37) % 61 - 30;
+            rocks.extend((x - (y % 7)..=x + 3).map(|x| (x, y)));
+            rocks.push((x - (y % 7), y - 1));
+        }
+        Cave::new(rocks, vec![(500, 0)])
+    }
+
+    #[test]
+    fn deep_cave_cross_check() {
+        let mut memoised = deep_cave(60);
+        let mut stepped = deep_cave(60);
+        assert_eq!(memoised.fill(), stepped.fill_by_steps());
+        memoised.add_floor(2);
+        stepped.add_floor(2);
+        let flood = memoised.flood().unwrap();
+        assert_eq!(memoised.fill(), flood);
+        assert_eq!(stepped.fill_by_steps(), flood);
+        let mut huge = deep_cave(300);
+        huge.add_floor(2);
+        assert_eq!(huge.fill(), huge.flood().unwrap());
+    }
+
+    #[test]
+    fn scan_matches_point_parser() {
+        let input = include_str!("../input.txt");
+        let paths = parse_scan(input).unwrap();
+        assert_eq!(paths.len(), input.lines().count());
+        let mut points = paths.iter().flatten().flat_map(Segment::points).collect::<Vec<Point>>();
+        points.sort_unstable();
+        points.dedup();
+        let expected = parse_input(input.to_string()).iter().map(|&(x, y)| (x as i64, y as i64)).collect::<Vec<Point>>();
+        assert_eq!(points, expected);
+    }
+
+    #[test]
+    fn segments() {
+        let paths = parse_scan("498,4 -> 498,6 -> 496,6\n\n  -3,0->-1,0\n").unwrap();
+        assert_eq!(paths[0], [Segment{from: (498, 4), to: (498, 6)}, Segment{from: (498, 6), to: (496, 6)}]);
+        assert_eq!(paths[0][1].points().collect::<Vec<Point>>(), [(498, 6), (497, 6), (496, 6)]);
+        assert_eq!(paths[1][0].points().count(), 3);
+    }
+
+    #[test]
+    fn scan_errors() {
+        assert_eq!(parse_scan("1,1 -> 1,3\n2,2 -> 4,4"), Err(ScanError::Diagonal{line: 2, segment: Segment{from: (2, 2), to: (4, 4)}}));
+        assert_eq!(parse_scan("1,1 -> 1,3 -> 1,3"), Err(ScanError::ZeroLength{line: 1, point: (1, 3)}));
+        assert_eq!(parse_scan("1,1 -> 1,3\n\n5,5\n"), Err(ScanError::TooFewPoints{line: 3}));
+        assert_eq!(parse_scan("1,1 -> 1;3"), Err(ScanError::BadPoint{line: 1, text: "1;3".to_string()}));
+        assert_eq!(parse_scan("1,1 -> "), Err(ScanError::BadPoint{line: 1, text: "".to_string()}));
+    }
+
+    #[test]
+    fn render_example() {
+        let mut cave = example_cave();
+        assert_eq!(cave.render(), "\
+......+...
+..........
+..........
+..........
+....#...##
+....#...#.
+..###...#.
+........#.
+........#.
+#########.
+");
+        cave.fill();
+        // the next grain falls to the left of the top of the pile
+        for _ in 0..3 {
+            cave.step();
+        }
+        assert_eq!(cave.render(), "\
+......+...
+..........
+.....~o...
+.....ooo..
+....#ooo##
+...o#ooo#.
+..###ooo#.
+....oooo#.
+.o.ooooo#.
+#########.
+");
+    }
 }
@@ -1,10 +1,13 @@
-use std::cmp::min;
+use std::cmp::{min, max};
 use std::io;
 use std::io::Read;
 use std::ops::RangeInclusive;
+use std::env;
+use std::fmt::Display;
 
 const BRUTE_FORCE_THRESHOLD: isize = 1000;
 const WINDOW_SIZE: isize = 400;
+const UNCOVERED_LIMIT: usize = 10000;
 
 struct Sensor {
     pos: (isize, isize),
@@ -25,6 +28,7 @@ impl Sensor {
         self.manhattan_distance(pos) <= self.scan_distance()
     }
 
+    #[cfg(test)]
     fn hidden_beacon_impossible_positions_in_row(&self, y: isize) -> Vec<isize> {
         let mut ret = vec![];
         let row_dist = (y - self.pos.1).abs();
@@ -37,6 +41,12 @@ impl Sensor {
         ret
     }
 
+    /// the x positions this sensor scans in row y, if it reaches that far
+    fn row_coverage(&self, y: isize) -> Option<RangeInclusive<isize>> {
+        let remaining_dist = self.scan_distance() - (y - self.pos.1).abs();
+        (remaining_dist >= 0).then(|| (self.pos.0 - remaining_dist)..=(self.pos.0 + remaining_dist))
+    }
+
     /// check whether each corner is within the Manhattan distance
     fn box_excluded(&self, top_corner: &(isize, isize), width: isize, height: isize) -> bool {
         [(0, 0), (0, height - 1), (width - 1, 0), (width - 1, height - 1)].iter().map(|add| (top_corner.0 + add.0, top_corner.1 + add.1))
@@ -48,15 +58,53 @@ fn main() {
     let mut buf = String::new();
     io::stdin().read_to_string(&mut buf).expect("Error reading stdin.");
     let sensors = parse_input(&buf);
+    let args = env::args().collect::<Vec<String>>();
+    let option = |name: &str, count: usize| args.iter().position(|arg| arg == name).map(|i| {
+        args[i + 1..].iter().take(count).map(|n| n.parse::<isize>().expect("Expected a number")).collect::<Vec<isize>>()
+    }).filter(|values| values.len() == count);
+    if let Some(values) = option("--map", 4) {
+        print!("{}", render_text(&raster(values[0]..=values[1], values[2] as usize, values[3] as usize, &sensors)));
+        return;
+    }
+    if let Some(values) = option("--ppm", 4) {
+        print!("{}", render_ppm(&raster(values[0]..=values[1], values[2] as usize, values[3] as usize, &sensors)));
+        return;
+    }
+    if let Some(values) = option("--gaps", 2) {
+        for gap in gaps(values[0]..=values[1], &sensors) {
+            println!("{}", gap);
+        }
+        return;
+    }
+    if let Some(row) = args.iter().position(|arg| arg == "--row").and_then(|i| args.get(i + 1)) {
+        let row = row.parse::<isize>().expect("Expected a row number");
+        let ranges = row_coverage(&sensors, row);
+        for range in &ranges {
+            println!("{}..={}", range.start(), range.end());
+        }
+        println!("{} positions in row {} where a beacon cannot be present", count_ranges(&ranges), row);
+        return;
+    }
     println!("{} positions in row {} where a beacon cannot be present", count_row(&sensors, 10), 10);
     println!("{} positions in row {} where a beacon cannot be present", count_row(&sensors, 2000000), 2000000);
 
-    if let Some(res) = find_hidden_beacon(0..=20, &sensors) {
-        println!("Hidden beacon at ({}, {}) with tuning frequency {}", res.0, res.1, tuning_frequency(res));
-    }
-
-    if let Some(res) = find_hidden_beacon(0..=4000000, &sensors) {
-        println!("Hidden beacon at ({}, {}) with tuning frequency {}", res.0, res.1, tuning_frequency(res));
+    for range in [0..=20, 0..=4000000] {
+        if args.iter().any(|arg| arg == "--quadtree") {
+            if let Some(res) = find_hidden_beacon(range, &sensors) {
+                println!("Hidden beacon at ({}, {}) with tuning frequency {}", res.0, res.1, tuning_frequency(res));
+            }
+            continue;
+        }
+        match find_uncovered(range.clone(), &sensors) {
+            Some(points) => for res in points {
+                println!("Hidden beacon at ({}, {}) with tuning frequency {}", res.0, res.1, tuning_frequency(res));
+            },
+            // too many places to list, so settle for the first the quadtree finds
+            None => if let Some(res) = find_hidden_beacon(range.clone(), &sensors) {
+                let places = gaps(range, &sensors).iter().map(|g| g.points).sum::<usize>();
+                println!("Hidden beacon at ({}, {}) with tuning frequency {}, one of {} places", res.0, res.1, tuning_frequency(res), places);
+            },
+        }
     }
 }
 
@@ -64,9 +112,7 @@ fn parse_input(input: &str) -> Vec<Sensor> {
     let mut ret = vec![];
     for line in input.lines() {
         let split = line.replace(['x', '=', 'y', ',', ':'], "").split(' ')
-            .map(|s| s.parse::<isize>())
-            .filter(|s| s.is_ok())
-            .map(|s| s.unwrap())
+            .filter_map(|s| s.parse::<isize>().ok())
             .collect::<Vec<isize>>();
         ret.push(Sensor {
             pos: (split[0], split[1]),
@@ -76,21 +122,307 @@ fn parse_input(input: &str) -> Vec<Sensor> {
     ret
 }
 
-fn count_row(sensors: &Vec<Sensor>, row: isize) -> usize {
-    let mut positions = vec![];
-    for s in sensors {
-        positions.append(&mut s.hidden_beacon_impossible_positions_in_row(row));
+fn count_row(sensors: &[Sensor], row: isize) -> usize {
+    count_ranges(&row_coverage(sensors, row))
+}
+
+fn count_ranges(ranges: &[RangeInclusive<isize>]) -> usize {
+    ranges.iter().map(|r| (r.end() - r.start() + 1) as usize).sum()
+}
+
+/// the sorted, disjoint ranges of x in the row where a beacon cannot be,
+/// which leaves out the known beacons
+fn row_coverage(sensors: &[Sensor], row: isize) -> Vec<RangeInclusive<isize>> {
+    let merged = merge_ranges(sensors.iter().filter_map(|s| s.row_coverage(row)).collect());
+    let mut beacons = sensors.iter().map(|s| s.nearest).filter(|b| b.1 == row).map(|b| b.0).collect::<Vec<isize>>();
+    beacons.sort_unstable();
+    beacons.dedup();
+    let mut ranges = vec![];
+    for range in merged {
+        let mut start = *range.start();
+        for &x in beacons.iter().filter(|x| range.contains(x)) {
+            if start < x {
+                ranges.push(start..=x - 1);
+            }
+            start = x + 1;
+        }
+        if start <= *range.end() {
+            ranges.push(start..=*range.end());
+        }
+    }
+    ranges
+}
+
+/// sorts the ranges and joins any that overlap or touch
+fn merge_ranges(mut ranges: Vec<RangeInclusive<isize>>) -> Vec<RangeInclusive<isize>> {
+    ranges.sort_unstable_by_key(|r| *r.start());
+    let mut merged: Vec<RangeInclusive<isize>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if *range.start() <= last.end() + 1 => {
+                *last = *last.start()..=*last.end().max(range.end());
+            },
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// u = x + y and v = x - y, which turns each scan into a square
+fn rotate(pos: (isize, isize)) -> (isize, isize) {
+    (pos.0 + pos.1, pos.0 - pos.1)
+}
+
+fn unrotate((u, v): (isize, isize)) -> (isize, isize) {
+    ((u + v) / 2, (u - v) / 2)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Axis {
+    U,
+    V,
+}
+
+/// the points outside every scan on the line where the u or v coordinate is c,
+/// within the square box, or None if there are more than the limit
+fn uncovered_on_line(sensors: &[Sensor], axis: Axis, c: isize, range: &RangeInclusive<isize>, limit: usize) -> Option<Vec<(isize, isize)>> {
+    // w is the other rotated coordinate, so x = (c + w) / 2 on either line
+    let (x_low, x_high) = (2 * range.start() - c, 2 * range.end() - c);
+    let (y_low, y_high) = match axis {
+        Axis::U => (c - 2 * range.end(), c - 2 * range.start()),
+        Axis::V => (c + 2 * range.start(), c + 2 * range.end()),
+    };
+    let (low, high) = (max(x_low, y_low), min(x_high, y_high));
+    let covered = merge_ranges(sensors.iter().filter_map(|s| {
+        let (u, v) = rotate(s.pos);
+        let (across, along) = if axis == Axis::U { (u, v) } else { (v, u) };
+        let r = s.scan_distance();
+        ((c - across).abs() <= r).then(|| (along - r)..=(along + r))
+    }).collect());
+    let point = |w: isize| unrotate(if axis == Axis::U { (c, w) } else { (w, c) });
+    // u and v always have the same parity
+    let parity = |w: isize| if (w - c).rem_euclid(2) == 0 { w } else { w + 1 };
+    let mut points = vec![];
+    let mut w = parity(low);
+    for range in covered.iter().chain([&((high + 1)..=(high + 1))]) {
+        while w < *range.start() && w <= high {
+            if points.len() == limit {
+                return None;
+            }
+            points.push(point(w));
+            w += 2;
+        }
+        w = w.max(parity(range.end() + 1));
+    }
+    Some(points)
+}
+
+/// every point in the square box outside all the scans, found on the lines
+/// just outside each scan, since an uncovered point next to a covered one
+/// must be on one. None if some uncovered point has no covered neighbour
+/// in the box, as then they can't all be found this way, or if the lines
+/// hold more than UNCOVERED_LIMIT points between them
+fn find_uncovered(range: RangeInclusive<isize>, sensors: &[Sensor]) -> Option<Vec<(isize, isize)>> {
+    let mut lines = sensors.iter().flat_map(|s| {
+        let (u, v) = rotate(s.pos);
+        let r = s.scan_distance() + 1;
+        [(Axis::U, u - r), (Axis::U, u + r), (Axis::V, v - r), (Axis::V, v + r)]
+    }).collect::<Vec<(Axis, isize)>>();
+    lines.sort_unstable_by_key(|&(axis, c)| (axis == Axis::V, c));
+    lines.dedup();
+    let mut points = vec![];
+    for &(axis, c) in &lines {
+        points.extend(uncovered_on_line(sensors, axis, c, &range, UNCOVERED_LIMIT - points.len())?);
+    }
+    points.sort_unstable();
+    points.dedup();
+    let uncovered = |p: (isize, isize)| range.contains(&p.0) && range.contains(&p.1) && !sensors.iter().any(|s| s.scan_contains(p));
+    if points.is_empty() {
+        // either everything is covered, or nothing is
+        return (range.is_empty() || !uncovered((*range.start(), *range.start()))).then_some(points);
+    }
+    let hidden = points.iter()
+        .flat_map(|p| [(p.0 - 1, p.1), (p.0 + 1, p.1), (p.0, p.1 - 1), (p.0, p.1 + 1)])
+        .any(|n| uncovered(n) && points.binary_search(&n).is_err());
+    (!hidden).then_some(points)
+}
+
+/// an uncovered region, as a rectangle in rotated coordinates
+#[derive(Debug, PartialEq)]
+struct Gap {
+    u: RangeInclusive<isize>,
+    v: RangeInclusive<isize>,
+    /// how many of the points in the rectangle are inside the box
+    points: usize,
+    /// one of those points
+    example: (isize, isize),
+}
+
+impl Display for Gap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let shape = if self.u.end() - self.u.start() == self.v.end() - self.v.start() { "diamond" } else { "rectangle" };
+        write!(f, "{} u={}..={} v={}..={}: {} point{} in the box",
+            shape, self.u.start(), self.u.end(), self.v.start(), self.v.end(), self.points, if self.points == 1 { "" } else { "s" })?;
+        if self.points == 1 {
+            write!(f, " at ({}, {})", self.example.0, self.example.1)?;
+        }
+        Ok(())
+    }
+}
+
+/// the uncovered parts of the square box: the scan edges split the rotated
+/// plane into rectangles that are either wholly covered by a scan or not,
+/// and the uncovered ones are joined into runs along v in each strip of u,
+/// with a run extended across the next strip only if it has exactly the
+/// same run, so this isn't the fewest rectangles possible
+fn gaps(range: RangeInclusive<isize>, sensors: &[Sensor]) -> Vec<Gap> {
+    let (low, high) = (*range.start(), *range.end());
+    let (u_range, v_range) = ((2 * low)..=(2 * high), (low - high)..=(high - low));
+    let scans = sensors.iter().map(|s| (rotate(s.pos), s.scan_distance())).collect::<Vec<((isize, isize), isize)>>();
+    // where a run of each rotated coordinate starts
+    let breaks = |start: isize, end: isize, centre: fn(&(isize, isize)) -> isize| {
+        let mut breaks = scans.iter().flat_map(|(c, r)| [centre(c) - r, centre(c) + r + 1])
+            .filter(|&b| b > start && b <= end)
+            .chain([start, end + 1])
+            .collect::<Vec<isize>>();
+        breaks.sort_unstable();
+        breaks.dedup();
+        breaks
+    };
+    let u_breaks = breaks(*u_range.start(), *u_range.end(), |c| c.0);
+    let v_breaks = breaks(*v_range.start(), *v_range.end(), |c| c.1);
+    let covered = |u: isize, v: isize| scans.iter().any(|((su, sv), r)| (u - su).abs() <= *r && (v - sv).abs() <= *r);
+    let mut rectangles: Vec<(RangeInclusive<isize>, RangeInclusive<isize>)> = vec![];
+    let mut open: Vec<usize> = vec![];
+    for u in u_breaks.windows(2) {
+        let mut runs: Vec<RangeInclusive<isize>> = vec![];
+        for v in v_breaks.windows(2) {
+            if covered(u[0], v[0]) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if *run.end() + 1 == v[0] => *run = *run.start()..=v[1] - 1,
+                _ => runs.push(v[0]..=v[1] - 1),
+            }
+        }
+        // carry on any rectangle whose run continues unchanged
+        let mut still_open = vec![];
+        for run in runs {
+            match open.iter().find(|&&i| rectangles[i].1 == run) {
+                Some(&i) => {
+                    rectangles[i].0 = *rectangles[i].0.start()..=u[1] - 1;
+                    still_open.push(i);
+                },
+                None => {
+                    still_open.push(rectangles.len());
+                    rectangles.push((u[0]..=u[1] - 1, run));
+                },
+            }
+        }
+        open = still_open;
+    }
+    rectangles.into_iter().filter_map(|(u, v)| {
+        let mut points = 0;
+        let mut example = None;
+        for u in u.clone() {
+            // the part of this diagonal inside the box
+            let v_low = (*v.start()).max(2 * low - u).max(u - 2 * high);
+            let v_high = (*v.end()).min(2 * high - u).min(u - 2 * low);
+            let first = if (v_low - u).rem_euclid(2) == 0 { v_low } else { v_low + 1 };
+            if first <= v_high {
+                points += ((v_high - first) / 2 + 1) as usize;
+                example.get_or_insert(unrotate((u, first)));
+            }
+        }
+        example.map(|example| Gap{u, v, points, example})
+    }).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cell {
+    Sensor,
+    Beacon,
+    /// holds a point of one of the gaps
+    Gap,
+    /// wholly inside one scan
+    Covered,
+    /// the middle is scanned, but not the whole cell by any one sensor
+    Edge,
+    Uncovered,
+}
+
+/// scales the square box down to width by height cells
+fn raster(range: RangeInclusive<isize>, width: usize, height: usize, sensors: &[Sensor]) -> Vec<Vec<Cell>> {
+    let span = (range.end() - range.start() + 1).max(0) as usize;
+    let (width, height) = (width.min(span), height.min(span));
+    let start = |i: usize, cells: usize| range.start() + (span * i / cells) as isize;
+    let mut cells = vec![];
+    for j in 0..height {
+        let (y, y_end) = (start(j, height), start(j + 1, height));
+        let mut row = vec![];
+        for i in 0..width {
+            let (x, x_end) = (start(i, width), start(i + 1, width));
+            let middle = ((x + x_end - 1) / 2, (y + y_end - 1) / 2);
+            row.push(if sensors.iter().any(|s| s.box_excluded(&(x, y), x_end - x, y_end - y)) {
+                Cell::Covered
+            } else if sensors.iter().any(|s| s.scan_contains(middle)) {
+                Cell::Edge
+            } else {
+                Cell::Uncovered
+            });
+        }
+        cells.push(row);
+    }
+    let cell = |p: (isize, isize)| (range.contains(&p.0) && range.contains(&p.1)).then(|| {
+        let offset = |n: isize, cells: usize| ((n - range.start()) as usize * cells / span, cells);
+        (offset(p.1, height).0, offset(p.0, width).0)
+    });
+    let marks = gaps(range.clone(), sensors).iter().map(|g| (g.example, Cell::Gap))
+        .chain(sensors.iter().map(|s| (s.nearest, Cell::Beacon)))
+        .chain(sensors.iter().map(|s| (s.pos, Cell::Sensor)))
+        .collect::<Vec<((isize, isize), Cell)>>();
+    for (p, mark) in marks {
+        if let Some((j, i)) = cell(p) {
+            cells[j][i] = mark;
+        }
     }
-    positions.sort_unstable();
-    positions.dedup();
-    positions.len()
+    cells
+}
+
+fn render_text(cells: &[Vec<Cell>]) -> String {
+    cells.iter().map(|row| row.iter().map(|c| match c {
+        Cell::Sensor => 'S',
+        Cell::Beacon => 'B',
+        Cell::Gap => '!',
+        Cell::Covered => '#',
+        Cell::Edge => '+',
+        Cell::Uncovered => '.',
+    }).chain(['\n']).collect::<String>()).collect()
+}
+
+/// a plain-text PPM image, a pixel per cell
+fn render_ppm(cells: &[Vec<Cell>]) -> String {
+    let mut image = format!("P3\n{} {}\n255\n", cells.first().map_or(0, |r| r.len()), cells.len());
+    for row in cells {
+        let pixels = row.iter().map(|c| match c {
+            Cell::Sensor => "255 0 0",
+            Cell::Beacon => "0 0 255",
+            Cell::Gap => "255 255 0",
+            Cell::Covered => "64 64 64",
+            Cell::Edge => "128 128 128",
+            Cell::Uncovered => "255 255 255",
+        }).collect::<Vec<&str>>();
+        image += &pixels.join(" ");
+        image.push('\n');
+    }
+    image
 }
 
 fn tuning_frequency(pos: (isize, isize)) -> isize {
     pos.0 * 4000000 + pos.1
 }
 
-fn find_in_box(sensors: &Vec<Sensor>, top_corner: &(isize, isize), height: isize, width: isize) -> Option<(isize, isize)> {
+fn find_in_box(sensors: &[Sensor], top_corner: &(isize, isize), height: isize, width: isize) -> Option<(isize, isize)> {
     if sensors.iter().any(|s| s.box_excluded(top_corner, width, height)) {
         return None;
     }
@@ -122,17 +454,15 @@ fn find_in_box(sensors: &Vec<Sensor>, top_corner: &(isize, isize), height: isize
         next_gen.push(((top_corner.0 + mid_width, top_corner.1 + mid_height), height - mid_height, width - mid_width));
     }
     next_gen.iter()
-        .map(|(top_corner, height, width)| find_in_box(sensors, top_corner, *height, *width))
-        .find(|o| o.is_some())
-        .map(|o| o.unwrap())
+        .find_map(|(top_corner, height, width)| find_in_box(sensors, top_corner, *height, *width))
 
 }
 
-fn find_hidden_beacon(range: RangeInclusive<isize>, sensors: &Vec<Sensor>) -> Option<(isize, isize)> {
+fn find_hidden_beacon(range: RangeInclusive<isize>, sensors: &[Sensor]) -> Option<(isize, isize)> {
     let window_size = WINDOW_SIZE;
     for x in range.clone().step_by(window_size as usize) {
         for y in range.clone().step_by(window_size as usize) {
-            let res = find_in_box(&sensors, &(x, y), min(window_size, range.end() - y), min(window_size, range.end() - x));
+            let res = find_in_box(sensors, &(x, y), min(window_size, range.end() - y), min(window_size, range.end() - x));
             if res.is_some() {
                 return res;
             }
@@ -140,3 +470,165 @@ fn find_hidden_beacon(range: RangeInclusive<isize>, sensors: &Vec<Sensor>) -> Op
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naive_count_row(sensors: &[Sensor], row: isize) -> usize {
+        let mut positions = vec![];
+        for s in sensors {
+            positions.append(&mut s.hidden_beacon_impossible_positions_in_row(row));
+        }
+        positions.sort_unstable();
+        positions.dedup();
+        positions.retain(|&x| !sensors.iter().any(|s| s.nearest == (x, row)));
+        positions.len()
+    }
+
+    #[test]
+    fn example_row() {
+        let sensors = parse_input(include_str!("../example.txt"));
+        // the beacon at (2, 10) splits the coverage
+        assert_eq!(row_coverage(&sensors, 10), [-2..=1, 3..=24]);
+        assert_eq!(count_row(&sensors, 10), 26);
+        assert_eq!(row_coverage(&sensors, 1000), []);
+    }
+
+    #[test]
+    fn sensor_row_coverage() {
+        let sensor = Sensor{pos: (8, 7), nearest: (2, 10)};
+        assert_eq!(sensor.row_coverage(7), Some(-1..=17));
+        assert_eq!(sensor.row_coverage(16), Some(8..=8));
+        assert_eq!(sensor.row_coverage(17), None);
+        assert_eq!(sensor.row_coverage(-2), Some(8..=8));
+    }
+
+    #[test]
+    fn matches_naive_count() {
+        let sensors = parse_input(include_str!("../example.txt"));
+        for row in -15..40 {
+            let ranges = row_coverage(&sensors, row);
+            assert!(ranges.windows(2).all(|w| w[0].end() < w[1].start()));
+            assert_eq!(count_ranges(&ranges), naive_count_row(&sensors, row), "row {}", row);
+        }
+    }
+
+    fn naive_uncovered(range: RangeInclusive<isize>, sensors: &[Sensor]) -> Vec<(isize, isize)> {
+        range.clone().flat_map(|x| range.clone().map(move |y| (x, y)))
+            .filter(|&p| !sensors.iter().any(|s| s.scan_contains(p)))
+            .collect()
+    }
+
+    #[test]
+    fn rotation() {
+        for p in [(0, 0), (3, -7), (-2, -2), (5, 1)] {
+            assert_eq!(unrotate(rotate(p)), p);
+        }
+        let sensors = [Sensor{pos: (8, 7), nearest: (2, 10)}];
+        // the line u = 15 + 10 runs along the outside of the top right edge
+        let points = uncovered_on_line(&sensors, Axis::U, 25, &(0..=20), 16).unwrap();
+        assert_eq!(points.len(), 16);
+        assert!(points.iter().all(|p| p.0 + p.1 == 25 && (5..=20).contains(&p.0)));
+        assert_eq!(uncovered_on_line(&sensors, Axis::U, 25, &(0..=20), 15), None);
+    }
+
+    #[test]
+    fn example_beacon() {
+        let sensors = parse_input(include_str!("../example.txt"));
+        assert_eq!(find_uncovered(0..=20, &sensors), Some(vec![(14, 11)]));
+        assert_eq!(find_hidden_beacon(0..=20, &sensors), Some((14, 11)));
+        assert_eq!(tuning_frequency((14, 11)), 56000011);
+    }
+
+    #[test]
+    fn too_many_to_list() {
+        let sensors = parse_input(include_str!("../example.txt"));
+        let start = std::time::Instant::now();
+        assert_eq!(find_uncovered(0..=4000000, &sensors), None);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    /// either every uncovered point is found, or some uncovered point has
+    /// no covered neighbour in the box
+    fn check_uncovered(range: RangeInclusive<isize>, sensors: &[Sensor]) {
+        let naive = naive_uncovered(range.clone(), sensors);
+        match find_uncovered(range.clone(), sensors) {
+            Some(points) => assert_eq!(points, naive),
+            None => assert!(naive.iter().any(|p| [(p.0 - 1, p.1), (p.0 + 1, p.1), (p.0, p.1 - 1), (p.0, p.1 + 1)].iter()
+                .all(|n| !range.contains(&n.0) || !range.contains(&n.1) || naive.contains(n)))),
+        }
+    }
+
+    #[test]
+    fn several_gaps() {
+        let sensors = parse_input(include_str!("../example.txt"));
+        for range in [-4..=24, -1..=21, 2..=22, 10..=30, 5..=6] {
+            check_uncovered(range, &sensors);
+        }
+        assert_eq!(find_uncovered(-1..=21, &sensors).map(|p| p.len()), Some(4));
+        // a diagonal gap a single square wide between two scans
+        let sensors = [Sensor{pos: (0, 0), nearest: (3, 0)}, Sensor{pos: (5, 5), nearest: (0, 5)}];
+        assert_eq!(find_uncovered(0..=4, &sensors), Some(vec![(0, 4), (1, 3), (2, 2), (3, 1), (4, 0)]));
+        assert_eq!(find_uncovered(-10..=10, &sensors), None);
+        assert_eq!(find_uncovered(100..=110, &sensors), None);
+        assert_eq!(find_uncovered(1..=1, &sensors), Some(vec![]));
+        for range in [-1..=4, 0..=5, -2..=8, 3..=9] {
+            check_uncovered(range, &sensors);
+        }
+    }
+
+    #[test]
+    fn matches_quadtree() {
+        let sensors = parse_input(include_str!("../input.txt"));
+        let points = find_uncovered(0..=4000000, &sensors).unwrap();
+        assert_eq!(points.len(), 1);
+        // the quadtree search is too slow for the whole area in a debug build
+        let near = (points[0].0.min(points[0].1) - 1000)..=(points[0].0.max(points[0].1) + 1000);
+        assert_eq!(find_uncovered(near.clone(), &sensors), Some(points));
+        assert_eq!(find_uncovered(near.clone(), &sensors).unwrap(), [find_hidden_beacon(near, &sensors).unwrap()]);
+    }
+
+    #[test]
+    fn gaps_count_every_uncovered_point() {
+        let example = parse_input(include_str!("../example.txt"));
+        let strip = [Sensor{pos: (0, 0), nearest: (3, 0)}, Sensor{pos: (5, 5), nearest: (0, 5)}];
+        for (sensors, range) in [(&example[..], -4..=24), (&example[..], 0..=20), (&example[..], 9..=13), (&strip[..], -3..=8), (&strip[..], 0..=4)] {
+            let gaps = gaps(range.clone(), sensors);
+            let naive = naive_uncovered(range.clone(), sensors);
+            assert_eq!(gaps.iter().map(|g| g.points).sum::<usize>(), naive.len());
+            assert!(gaps.iter().all(|g| naive.contains(&g.example) && g.u.contains(&rotate(g.example).0) && g.v.contains(&rotate(g.example).1)));
+        }
+        assert_eq!(gaps(0..=20, &example), [Gap{u: 25..=25, v: 3..=3, points: 1, example: (14, 11)}]);
+        assert_eq!(gaps(0..=4, &strip), [Gap{u: 4..=4, v: -4..=4, points: 5, example: (0, 4)}]);
+        assert_eq!(gaps(0..=4, &strip)[0].to_string(), "rectangle u=4..=4 v=-4..=4: 5 points in the box");
+    }
+
+    #[test]
+    fn input_gap() {
+        let sensors = parse_input(include_str!("../input.txt"));
+        let gaps = gaps(0..=4000000, &sensors);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].points, 1);
+        assert_eq!(Some(vec![gaps[0].example]), find_uncovered(0..=4000000, &sensors));
+    }
+
+    #[test]
+    fn render_example() {
+        let sensors = parse_input(include_str!("../example.txt"));
+        let full = render_text(&raster(0..=20, 21, 21, &sensors));
+        assert_eq!(full.lines().nth(11), Some("S#############!######"));
+        assert_eq!(full.lines().nth(16), Some("#########SB##########"));
+        // scaled down, each gap outside the scans marks the cell where it starts
+        assert_eq!(render_text(&raster(-10..=30, 8, 4, &sensors)), "\
+!+S+!.!.
+!+BSSS!.
+!SSSSSB+
+!!!.!+B.
+");
+        assert_eq!(render_text(&raster(0..=2, 10, 10, &sensors)).lines().count(), 3);
+        let ppm = render_ppm(&raster(0..=20, 4, 2, &sensors));
+        assert!(ppm.starts_with("P3\n4 2\n255\n"));
+        assert_eq!(ppm.lines().count(), 5);
+    }
+}